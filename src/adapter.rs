@@ -0,0 +1,141 @@
+use std::io::{Error, Read, Write};
+
+use crate::config::Config;
+use crate::stats::Stats;
+use crate::streaming::StreamingCounter;
+
+/// A reader passing data through unchanged, while counting it. Data is counted as UTF-8 as it
+/// is read, without any decompression or transcoding.
+///
+/// ```
+/// use std::io::Read;
+/// use awc::{Config, CountingReader};
+///
+/// let mut reader = CountingReader::new(&b"hello world\n"[..], &Config::all());
+/// let mut copy = String::new();
+/// reader.read_to_string(&mut copy)?;
+///
+/// let stats = reader.finish()?;
+/// assert_eq!(copy, "hello world\n");
+/// assert_eq!(stats.words, 2);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct CountingReader<R> {
+    inner: R,
+    counter: StreamingCounter,
+}
+
+impl<R: Read> CountingReader<R> {
+    /// count what's read from inner, as requested by the configuration
+    pub fn new(inner: R, config: &Config) -> CountingReader<R> {
+        CountingReader {
+            inner,
+            counter: StreamingCounter::new(config),
+        }
+    }
+
+    /// get a reference to the inner reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// get the statistics of the data read so far. Fails on invalid UTF-8 if the configuration
+    /// says so
+    pub fn finish(self) -> Result<Stats, Error> {
+        self.counter.finish()
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let n = self.inner.read(buf)?;
+        self.counter.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// A writer passing data through unchanged, while counting it. Only the data accepted by the
+/// inner writer is counted.
+pub struct CountingWriter<W> {
+    inner: W,
+    counter: StreamingCounter,
+}
+
+impl<W: Write> CountingWriter<W> {
+    /// count what's written to inner, as requested by the configuration
+    pub fn new(inner: W, config: &Config) -> CountingWriter<W> {
+        CountingWriter {
+            inner,
+            counter: StreamingCounter::new(config),
+        }
+    }
+
+    /// get a reference to the inner writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// get the statistics of the data written so far. Fails on invalid UTF-8 if the
+    /// configuration says so
+    pub fn finish(self) -> Result<Stats, Error> {
+        self.counter.finish()
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let n = self.inner.write(buf)?;
+        self.counter.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::Counter;
+
+    #[test]
+    fn pass_through() {
+        let config = Config::all();
+
+        for file_name in &["tests/poe.unix", "tests/utf8.txt"] {
+            let data = std::fs::read(file_name).unwrap();
+            let expected = Counter::read_file(&data[..], &config).unwrap();
+
+            // small reads and writes
+            let mut reader = CountingReader::new(&data[..], &config);
+            let mut writer = CountingWriter::new(Vec::new(), &config);
+            let mut buf = [0u8; 5];
+            loop {
+                let n = reader.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                writer.write_all(&buf[..n]).unwrap();
+            }
+            assert_eq!(writer.get_ref(), &data);
+
+            for stats in &[reader.finish().unwrap(), writer.finish().unwrap()] {
+                assert_eq!(stats.bytes, expected.bytes);
+                assert_eq!(stats.chars, expected.chars);
+                assert_eq!(stats.words, expected.words);
+                assert_eq!(stats.lines, expected.lines);
+                assert_eq!(stats.max_line, expected.max_line);
+                assert_eq!(stats.min_line, expected.min_line);
+            }
+        }
+
+        // data is passed through even if it can't be counted
+        let data = std::fs::read("tests/invalid.txt").unwrap();
+        let mut reader = CountingReader::new(&data[..], &config);
+        let mut copy = Vec::new();
+        std::io::copy(&mut reader, &mut copy).unwrap();
+        assert_eq!(copy, data);
+        assert!(reader.finish().is_err());
+    }
+}
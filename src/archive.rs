@@ -0,0 +1,317 @@
+use std::fs::File;
+#[cfg(any(feature = "zip", feature = "tar"))]
+use std::io::BufRead;
+use std::io::{BufReader, Cursor, Error, ErrorKind, Read};
+use std::path::Path;
+
+use crate::compression::{Compression, MAGIC_SIZE};
+use crate::config::Config;
+#[cfg(any(feature = "zip", feature = "tar"))]
+use crate::counter::Counter;
+use crate::counter::{open, read_head, ByteCounter};
+#[cfg(any(feature = "zip", feature = "tar"))]
+use crate::decode::TextEncoding;
+use crate::stats::Stats;
+
+// size of a tar header block
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// counts for a single archive member, named after the archive and the member path
+pub type EntryStats = (String, Result<Stats, Error>);
+
+/// Counts of all the members of an archive
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ArchiveStats {
+    /// counts of each member, in the order they are stored
+    pub entries: Vec<EntryStats>,
+
+    /// size of the archive, as read from the file or the standard input
+    pub compressed_bytes: u64,
+}
+
+/// Archive formats for which each member is counted separately
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+}
+
+impl ArchiveFormat {
+    /// guess the archive format from the first bytes of the input. For tar, the first header
+    /// block is needed
+    pub fn from_magic(buf: &[u8]) -> Option<ArchiveFormat> {
+        // a local file header, or the end of central directory for an empty archive
+        if buf.starts_with(b"PK\x03\x04") || buf.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        // either POSIX or GNU magic
+        } else if buf.len() >= TAR_BLOCK_SIZE && &buf[257..262] == b"ustar" {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Archive;
+
+impl Archive {
+    /// count each regular member of the archive. Directories and binary members are skipped.
+    /// Tar archives might be compressed and read from the standard input
+    pub fn count<P: AsRef<Path>>(file_name: P, options: &Config) -> Result<ArchiveStats, Error> {
+        let path = file_name.as_ref();
+
+        // keep track of the bytes read from the archive, before decompression
+        let mut file = ByteCounter::new(open(path)?);
+        let head = read_head(&mut file, MAGIC_SIZE)?;
+
+        // zip archives need to seek to the central directory, so they're reopened as files
+        if ArchiveFormat::from_magic(&head) == Some(ArchiveFormat::Zip) {
+            if path == Path::new("-") {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "zip archives can't be read from the standard input",
+                ));
+            }
+            let file = File::open(path)?;
+            let compressed_bytes = file.metadata()?.len();
+            return Ok(ArchiveStats {
+                entries: Archive::count_zip(path, file, options)?,
+                compressed_bytes,
+            });
+        }
+
+        // the head is put back in front of the remaining data
+        let compression = Compression::select(&head, options);
+        let reader = BufReader::new(Cursor::new(head).chain(&mut file));
+        let mut reader: Box<dyn Read + Send + '_> = match compression {
+            Some(compression) => compression.decoder(reader)?,
+            None => Box::new(reader),
+        };
+
+        // the first block is put back in front of the remaining data
+        let mut block = Vec::with_capacity(TAR_BLOCK_SIZE);
+        reader
+            .by_ref()
+            .take(TAR_BLOCK_SIZE as u64)
+            .read_to_end(&mut block)?;
+
+        let entries = match ArchiveFormat::from_magic(&block) {
+            Some(ArchiveFormat::Tar) => {
+                Archive::count_tar(path, Cursor::new(block).chain(reader), options)?
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "not a supported archive",
+                ))
+            }
+        };
+
+        Ok(ArchiveStats {
+            entries,
+            compressed_bytes: file.bytes,
+        })
+    }
+
+    // walk the central directory and count each member in turn
+    #[cfg(feature = "zip")]
+    fn count_zip(path: &Path, file: File, options: &Config) -> Result<Vec<EntryStats>, Error> {
+        let mut archive = zip::ZipArchive::new(file)?;
+        let mut entries = Vec::new();
+
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i)?;
+            if entry.is_dir() {
+                continue;
+            }
+
+            let name = format!("{}!{}", path.display(), entry.name());
+            let compressed_size = entry.compressed_size();
+            let mut reader = BufReader::new(entry);
+
+            let stats = match reader.fill_buf() {
+                Ok(head) if is_binary(head, options) => continue,
+                Ok(_) => Counter::count_reader(reader, options).map(|mut stats| {
+                    stats.compressed_bytes = compressed_size;
+                    stats
+                }),
+                Err(e) => Err(e),
+            };
+
+            entries.push((name, stats));
+        }
+
+        Ok(entries)
+    }
+
+    #[cfg(not(feature = "zip"))]
+    fn count_zip(_: &Path, _: File, _: &Config) -> Result<Vec<EntryStats>, Error> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "awc was built without Zip support",
+        ))
+    }
+
+    // stream the tar archive and count each regular file in turn, without extracting anything
+    #[cfg(feature = "tar")]
+    fn count_tar<R: Read>(
+        path: &Path,
+        reader: R,
+        options: &Config,
+    ) -> Result<Vec<EntryStats>, Error> {
+        let mut archive = tar::Archive::new(reader);
+        let mut entries = Vec::new();
+
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let name = format!("{}!{}", path.display(), entry.path()?.display());
+            let mut reader = BufReader::new(entry);
+
+            let stats = match reader.fill_buf() {
+                Ok(head) if is_binary(head, options) => continue,
+                Ok(_) => Counter::count_reader(reader, options),
+                Err(e) => Err(e),
+            };
+
+            entries.push((name, stats));
+        }
+
+        // the padding after the last member is part of the archive size
+        std::io::copy(&mut archive.into_inner(), &mut std::io::sink())?;
+
+        Ok(entries)
+    }
+
+    #[cfg(not(feature = "tar"))]
+    fn count_tar<R: Read>(_: &Path, _: R, _: &Config) -> Result<Vec<EntryStats>, Error> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "awc was built without Tar support",
+        ))
+    }
+}
+
+// like most tools, consider data holding a NUL byte as binary, unless it's UTF-16 or UTF-32
+#[cfg(any(feature = "zip", feature = "tar"))]
+fn is_binary(head: &[u8], options: &Config) -> bool {
+    let encoding = match TextEncoding::from_bom(head) {
+        Some((encoding, _)) => Some(encoding),
+        None => options.encoding,
+    };
+    let wide = !matches!(
+        encoding,
+        None | Some(TextEncoding::Utf8) | Some(TextEncoding::Legacy(_))
+    );
+
+    !wide && head.contains(&0)
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+    #[cfg(any(feature = "zip", feature = "tar"))]
+    use super::*;
+
+    #[test]
+    #[cfg(all(target_family = "unix", feature = "zip"))]
+    fn count_zip() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.chars = true;
+        options.words = true;
+        options.lines = true;
+
+        let archive = Archive::count("tests/bundle.zip", &options);
+        assert!(archive.is_ok());
+
+        // the directory and the gzipped file are skipped
+        let archive = archive.unwrap();
+        assert_eq!(
+            archive.compressed_bytes,
+            std::fs::metadata("tests/bundle.zip").unwrap().len()
+        );
+        let entries = archive.entries;
+        let names: Vec<&str> = entries.iter().map(|e| e.0.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "tests/bundle.zip!poe.unix",
+                "tests/bundle.zip!greek/odysseus.unix",
+                "tests/bundle.zip!utf16le.txt"
+            ]
+        );
+
+        let stats = entries[0].1.as_ref().unwrap();
+        assert_eq!(stats.bytes, 25260);
+        assert_eq!(stats.chars, 25258);
+        assert_eq!(stats.words, 3969);
+        assert_eq!(stats.lines, 887);
+
+        let stats = entries[1].1.as_ref().unwrap();
+        assert_eq!(stats.bytes, 3776);
+        assert_eq!(stats.chars, 1935);
+        assert_eq!(stats.words, 304);
+        assert_eq!(stats.lines, 44);
+
+        let stats = entries[2].1.as_ref().unwrap();
+        assert_eq!(stats.bytes, 2002);
+        assert_eq!(stats.chars, 1000);
+
+        // not an archive
+        assert!(Archive::count("tests/poe.unix", &options).is_err());
+    }
+
+    #[test]
+    #[cfg(all(target_family = "unix", feature = "tar", feature = "zstd"))]
+    fn count_tar() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.chars = true;
+        options.words = true;
+        options.lines = true;
+
+        // same archive, either plain or compressed
+        for archive in &["tests/bundle.tar", "tests/bundle.tar.zst"] {
+            let stats = Archive::count(archive, &options);
+            assert!(stats.is_ok());
+
+            // --ratio uses the size of the archive itself, not of its decompressed data
+            let stats = stats.unwrap();
+            assert_eq!(
+                stats.compressed_bytes,
+                std::fs::metadata(archive).unwrap().len()
+            );
+
+            // the directory and the gzipped file are skipped
+            let entries = stats.entries;
+            let names: Vec<String> = entries.iter().map(|e| e.0.clone()).collect();
+            assert_eq!(
+                names,
+                vec![
+                    format!("{}!poe.unix", archive),
+                    format!("{}!greek/odysseus.unix", archive),
+                    format!("{}!utf16le.txt", archive),
+                ]
+            );
+
+            let stats = entries[0].1.as_ref().unwrap();
+            assert_eq!(stats.bytes, 25260);
+            assert_eq!(stats.chars, 25258);
+            assert_eq!(stats.words, 3969);
+            assert_eq!(stats.lines, 887);
+
+            let stats = entries[1].1.as_ref().unwrap();
+            assert_eq!(stats.bytes, 3776);
+            assert_eq!(stats.chars, 1935);
+            assert_eq!(stats.words, 304);
+            assert_eq!(stats.lines, 44);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::io::{Cursor, Error, ErrorKind};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_compression::tokio::bufread::GzipDecoder;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader, ReadBuf};
+
+use crate::compression::{Compression, GzipHeader};
+use crate::config::Config;
+use crate::counter::{Counter, Input};
+use crate::stats::Stats;
+
+// Async versions of the counting functions, for services which can't afford a blocking thread
+// per input. Reading is the only difference: the encoding is selected, and data transcoded and
+// counted, by the same Input as the one of count_reader()
+impl Counter {
+    /// count a file, "-" being the standard input, without blocking the thread. Unless disabled
+    /// in the configuration, gzipped files are transparently decompressed. Other compression
+    /// formats are not supported, as in [`Counter::count_async`]
+    pub async fn count_file_async<P: AsRef<Path>>(
+        file_name: P,
+        options: &Config,
+    ) -> Result<Stats, Error> {
+        let path = file_name.as_ref();
+
+        if path == Path::new("-") {
+            Counter::count_async(BufReader::new(tokio::io::stdin()), options).await
+        } else {
+            let file = tokio::fs::File::open(path).await?;
+            Counter::count_async(BufReader::new(file), options).await
+        }
+    }
+
+    /// count any async reader, e.g. an upload as it streams through. Unless disabled in the
+    /// configuration, gzipped data is transparently decompressed. Bzip2, xz, zstd and lz4 data,
+    /// either detected or given by [`Config::decompress`], is not supported and gives an
+    /// [`ErrorKind::Unsupported`] error, even when their cargo feature is enabled
+    pub async fn count_async<R: AsyncBufRead + Unpin>(
+        reader: R,
+        options: &Config,
+    ) -> Result<Stats, Error> {
+        // keep track of the bytes read, before decompression
+        let mut raw = AsyncByteCounter::new(reader);
+
+        // a single read can be shorter than the magic number, so the head is read in full, and
+        // put back in front of the remaining data
+        let mut head = Vec::with_capacity(Compression::head_size(options));
+        (&mut raw)
+            .take(head.capacity() as u64)
+            .read_to_end(&mut head)
+            .await?;
+        let compression = Compression::select(&head, options);
+
+        let header = if options.gzip_header && compression == Some(Compression::Gzip) {
+            GzipHeader::from_head(&head)
+        } else {
+            None
+        };
+
+        let reader = Cursor::new(head).chain(&mut raw);
+        let mut stats = match compression {
+            None => Counter::count_reader_async(reader, options).await?,
+
+            // concatenated members, as in BGZF files, are all decoded
+            Some(Compression::Gzip) => {
+                let mut decoder = GzipDecoder::new(reader);
+                decoder.multiple_members(true);
+                Counter::count_reader_async(BufReader::new(decoder), options).await?
+            }
+
+            Some(compression) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("{:?} data can't be counted asynchronously", compression),
+                ))
+            }
+        };
+
+        stats.compressed_bytes = raw.bytes;
+
+        if let Some(header) = header {
+            stats.original_name = header.name;
+            stats.mtime = Some(header.mtime);
+        }
+
+        Ok(stats)
+    }
+
+    // same as count_reader()
+    async fn count_reader_async<R: AsyncBufRead + Unpin>(
+        reader: R,
+        options: &Config,
+    ) -> Result<Stats, Error> {
+        let mut raw = AsyncByteCounter::new(reader);
+
+        // the beginning of the input is kept aside to look for a BOM or to guess the encoding
+        let mut head = Vec::with_capacity(Counter::head_size(options));
+        let result = (&mut raw)
+            .take(head.capacity() as u64)
+            .read_to_end(&mut head)
+            .await;
+        let mut input = Input::new(&head, result, options)?;
+
+        if input.read_more() {
+            loop {
+                let buffer = match raw.fill_buf().await {
+                    Ok(buffer) => buffer,
+                    Err(e) => match input.retry(e)? {
+                        true => continue,
+                        false => break,
+                    },
+                };
+
+                // did we meet EOF?
+                if buffer.is_empty() {
+                    break;
+                }
+
+                let length = buffer.len();
+                let more = input.update(buffer)?;
+                raw.consume(length);
+
+                // no need to read further, the file is in error
+                if !more {
+                    break;
+                }
+            }
+        }
+
+        input.finish(raw.bytes, options)
+    }
+}
+
+// Same as ByteCounter, for async readers: count bytes as they're consumed
+struct AsyncByteCounter<R> {
+    inner: R,
+    bytes: u64,
+}
+
+impl<R> AsyncByteCounter<R> {
+    fn new(inner: R) -> AsyncByteCounter<R> {
+        AsyncByteCounter { inner, bytes: 0 }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for AsyncByteCounter<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.bytes += (buf.filled().len() - filled) as u64;
+        result
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for AsyncByteCounter<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<&[u8], Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.bytes += amt as u64;
+        Pin::new(&mut self.inner).consume(amt);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+    use super::*;
+    use crate::config::InvalidMode;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // same results as the sync counting
+    fn check(file_name: &str, options: &Config) {
+        let expected = Counter::count(file_name, options);
+        let stats = block_on(Counter::count_file_async(file_name, options));
+
+        match (expected, stats) {
+            (Ok(expected), Ok(stats)) => {
+                assert_eq!(stats.bytes, expected.bytes, "{}", file_name);
+                assert_eq!(stats.chars, expected.chars, "{}", file_name);
+                assert_eq!(stats.words, expected.words, "{}", file_name);
+                assert_eq!(stats.lines, expected.lines, "{}", file_name);
+                assert_eq!(stats.max_line, expected.max_line, "{}", file_name);
+                assert_eq!(stats.min_line, expected.min_line, "{}", file_name);
+                assert_eq!(stats.compressed_bytes, expected.compressed_bytes);
+                assert_eq!(stats.invalid_sequences, expected.invalid_sequences);
+                assert_eq!(stats.encoding, expected.encoding, "{}", file_name);
+                assert_eq!(stats.partial.is_some(), expected.partial.is_some());
+                assert_eq!(stats.original_name, expected.original_name);
+            }
+            (Err(expected), Err(e)) => assert_eq!(e.kind(), expected.kind(), "{}", file_name),
+            (expected, stats) => panic!("{}: {:?} != {:?}", file_name, stats, expected),
+        }
+    }
+
+    #[test]
+    fn count_file_async() {
+        let mut options = Config::all();
+        options.jobs = 1;
+
+        for file_name in &[
+            "tests/poe.unix",
+            "tests/odysseus.unix",
+            "tests/utf8.txt",
+            "tests/invalid.txt",
+            "tests/utf16le.txt",
+            "tests/utf32be.txt",
+            "tests/poe.unix.gz",
+            "tests/poe.unix.multi.gz",
+            "tests/poe.unix.truncated.gz",
+        ] {
+            check(file_name, &options);
+        }
+
+        let mut detected = options.clone();
+        detected.detect_encoding = true;
+        detected.invalid = InvalidMode::Report;
+        for file_name in &[
+            "tests/french.windows-1252",
+            "tests/japanese.shift_jis",
+            "tests/utf16le.nobom.txt",
+        ] {
+            check(file_name, &detected);
+        }
+
+        let mut salvaged = options.clone();
+        salvaged.salvage = true;
+        salvaged.gzip_header = true;
+        check("tests/poe.unix.truncated.gz", &salvaged);
+        check("tests/poe.unix.corrupt.gz", &salvaged);
+
+        let mut bytes = Config::default();
+        bytes.bytes = true;
+        bytes.words = true;
+        check("tests/poe.unix.gz", &bytes);
+    }
+
+    #[test]
+    fn count_async() {
+        // data given in small pieces
+        let data = std::fs::read("tests/utf8.txt").unwrap();
+        let reader = BufReader::with_capacity(3, &data[..]);
+        let stats = block_on(Counter::count_async(reader, &Config::all())).unwrap();
+        let expected = Counter::count_reader(&data[..], &Config::all()).unwrap();
+
+        assert_eq!(stats.chars, expected.chars);
+        assert_eq!(stats.words, expected.words);
+        assert_eq!(stats.max_line, expected.max_line);
+
+        // compression selected even when reads are shorter than its magic number
+        let mut options = Config::all();
+        options.gzip_header = true;
+        let data = std::fs::read("tests/poe.unix.gz").unwrap();
+        let reader = BufReader::with_capacity(1, &data[..]);
+        let stats = block_on(Counter::count_async(reader, &options)).unwrap();
+        let expected = Counter::count("tests/poe.unix.gz", &options).unwrap();
+
+        assert_eq!(stats.lines, 887);
+        assert_eq!(stats.compressed_bytes, data.len() as u64);
+        assert_eq!(stats.original_name, expected.original_name);
+        assert_eq!(stats.mtime, expected.mtime);
+
+        // only gzip can be decompressed
+        let data = std::fs::read("tests/poe.unix.xz").unwrap();
+        let error = block_on(Counter::count_async(&data[..], &Config::all())).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+        // even when requested
+        let mut options = Config::all();
+        options.decompress = Some(Compression::Zstd);
+        let error = block_on(Counter::count_async(&b"hello\n"[..], &options)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+}
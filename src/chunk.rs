@@ -0,0 +1,661 @@
+use std::io::{Error, ErrorKind};
+
+use crate::config::{Config, InvalidMode};
+use crate::scan;
+use crate::stats::Stats;
+
+// Counts of a contiguous part of the input, with enough state on both sides to be merged with
+// the counts of the parts before and after it. This allows to count ranges of the same file in
+// any order, and get the same results as when counting the whole file at once.
+//
+// Leading continuation bytes and a trailing incomplete UTF-8 sequence can't be decoded without
+// their neighbours, so they're kept aside until the chunk is merged or finished.
+#[derive(Debug)]
+pub struct ChunkStats {
+    // what's needed from the data
+    needs: Needs,
+
+    // up to 3 continuation bytes at the beginning, which might end a sequence started before
+    lead: Vec<u8>,
+
+    // counts of the data between lead and trail
+    body: Body,
+
+    // incomplete sequence at the end, which might be completed by the next chunk
+    trail: Vec<u8>,
+}
+
+impl ChunkStats {
+    // even when data is not decoded, sequences are kept whole so that Unicode whitespaces split
+    // between chunks are still recognised
+    pub fn new(buf: &[u8], opt: &Config) -> ChunkStats {
+        let needs = Needs::new(opt);
+
+        let lead = lead_length(buf);
+        let trail = lead + trail_start(&buf[lead..]);
+
+        ChunkStats {
+            needs,
+            lead: buf[..lead].to_vec(),
+            body: Body::scan(&buf[lead..trail], needs),
+            trail: buf[trail..].to_vec(),
+        }
+    }
+
+    // merge with the counts of the data immediately following this chunk
+    pub fn merge(self, other: ChunkStats) -> ChunkStats {
+        let needs = self.needs;
+
+        // only made of continuation bytes: they're still waiting for what comes before
+        if self.body.bytes == 0 && self.trail.is_empty() {
+            let mut lead = self.lead;
+            lead.extend_from_slice(&other.lead);
+
+            // a sequence can't have more than 3 continuation bytes, so the next ones are invalid
+            // whatever comes before
+            let extra = lead.split_off(lead.len().min(3));
+
+            return ChunkStats {
+                needs,
+                lead,
+                body: Body::scan(&extra, needs).merge(other.body),
+                trail: other.trail,
+            };
+        }
+
+        // the end of this chunk and the beginning of the other one are decoded together
+        let mut junction = self.trail;
+        junction.extend_from_slice(&other.lead);
+
+        let (body, trail) = if other.body.bytes == 0 {
+            // the other chunk doesn't hold a full sequence, so the junction might still be
+            // incomplete
+            junction.extend_from_slice(&other.trail);
+            let trail = junction.split_off(trail_start(&junction));
+            (Body::scan(&junction, needs), trail)
+        } else {
+            (Body::scan(&junction, needs).merge(other.body), other.trail)
+        };
+
+        ChunkStats {
+            needs,
+            lead: self.lead,
+            body: self.body.merge(body),
+            trail,
+        }
+    }
+
+    // true if an invalid UTF-8 sequence was met
+    pub fn has_invalid(&self) -> bool {
+        self.body.first_invalid.is_some()
+    }
+
+    // nothing comes before or after: convert to the final statistics, as if the whole data was
+    // counted at once
+    pub fn finish(self, opt: &Config) -> Result<Stats, Error> {
+        let body = Body::scan(&self.lead, self.needs)
+            .merge(self.body)
+            .merge(Body::scan(&self.trail, self.needs));
+
+        if let Some((offset, newlines)) = body.first_invalid {
+            if opt.invalid == InvalidMode::Strict {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "invalid UTF-8 sequence at byte offset {}, line {}",
+                        offset,
+                        newlines + 1
+                    ),
+                ));
+            }
+        }
+
+        let mut stats = Stats::default();
+
+        // the last line is counted even if it doesn't end with a newline
+        if opt.lines {
+            stats.lines = body.newlines + u64::from(body.tail > 0);
+        }
+
+        if opt.bytes {
+            stats.bytes = body.bytes;
+        }
+
+        if opt.chars {
+            stats.chars = body.chars;
+        }
+
+        if opt.words {
+            stats.words = body.words;
+        }
+
+        stats.invalid_sequences = body.invalid_sequences;
+
+        // first and last lines are only known now
+        let (mut max_line, mut min_line) = (body.max_line, body.min_line);
+        let mut line = |length: u64| {
+            max_line = max_line.max(length);
+            min_line = min_line.min(length);
+        };
+
+        if body.newlines > 0 {
+            line(line_length(body.head, body.head_cr));
+        }
+        if body.tail > 0 {
+            line(body.tail);
+        }
+
+        if opt.max_line {
+            stats.max_line = max_line;
+        }
+
+        // sanity check for empty files
+        if opt.min_line && min_line != u64::MAX {
+            stats.min_line = min_line;
+        }
+
+        Ok(stats)
+    }
+}
+
+// What's needed from the data, so that the work for counts nobody asked for is skipped
+#[derive(Debug, Clone, Copy)]
+struct Needs {
+    // true if data is decoded as UTF-8, false if only bytes are looked at
+    utf8: bool,
+
+    chars: bool,
+    words: bool,
+    lines: bool,
+
+    // lengths of the lines, which are otherwise only counted
+    lengths: bool,
+}
+
+impl Needs {
+    fn new(opt: &Config) -> Needs {
+        Needs {
+            utf8: opt.decode_utf8(),
+            chars: opt.chars,
+            words: opt.words,
+            lines: opt.lines,
+            lengths: opt.max_line || opt.min_line,
+        }
+    }
+}
+
+// Counts of data which doesn't need its neighbours to be decoded. Lines which start or end
+// outside of it are only known by their lengths on each side.
+#[derive(Debug)]
+struct Body {
+    bytes: u64,
+    chars: u64,
+    words: u64,
+    newlines: u64,
+    invalid_sequences: u64,
+
+    // offset and number of newlines before the first invalid sequence
+    first_invalid: Option<(u64, u64)>,
+
+    // whether the data starts or ends with a whitespace, to merge words split between chunks.
+    // Unless words are counted, there's no word to merge
+    first_blank: bool,
+    last_blank: bool,
+
+    // length of the data before the first newline, and after the last one
+    head: u64,
+    tail: u64,
+
+    // whether the first newline follows a carriage return, and whether the data ends with one
+    head_cr: bool,
+    last_cr: bool,
+
+    // longest and shortest lines which are entirely inside the data
+    max_line: u64,
+    min_line: u64,
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body {
+            bytes: 0,
+            chars: 0,
+            words: 0,
+            newlines: 0,
+            invalid_sequences: 0,
+            first_invalid: None,
+            first_blank: true,
+            last_blank: true,
+            head: 0,
+            tail: 0,
+            head_cr: false,
+            last_cr: false,
+            max_line: 0,
+            min_line: u64::MAX,
+        }
+    }
+}
+
+impl Body {
+    // count data. When decoding UTF-8, invalid sequences are replaced by U+FFFD. Whole data is
+    // counted at once, lines being only walked through when their lengths are needed
+    fn scan(buf: &[u8], needs: Needs) -> Body {
+        let mut body = Body::default();
+        if buf.is_empty() {
+            return body;
+        }
+
+        body.bytes = buf.len() as u64;
+
+        // Unicode whitespaces are recognised on raw bytes, which gives the same words as once
+        // decoded
+        if needs.words {
+            body.words = scan::count_unicode_words(buf, &mut false);
+            body.first_blank = scan::starts_with_blank(buf);
+            body.last_blank = scan::ends_with_blank(buf);
+        }
+
+        if needs.utf8 {
+            body.decode(buf, needs);
+        }
+
+        if needs.lines && !needs.lengths {
+            body.newlines = scan::count_newlines(buf);
+            body.byte_lines(buf);
+        }
+
+        body
+    }
+
+    // validate data, and count its chars and walk through its lines if needed
+    fn decode(&mut self, buf: &[u8], needs: Needs) {
+        let mut offset = 0;
+
+        for chunk in buf.utf8_chunks() {
+            let valid = chunk.valid();
+            if needs.chars {
+                self.chars += scan::count_chars(valid.as_bytes());
+            }
+            if needs.lengths {
+                self.push_lines(valid);
+            }
+            offset += valid.len();
+
+            let invalid = chunk.invalid();
+            if !invalid.is_empty() {
+                if self.first_invalid.is_none() {
+                    let newlines = scan::count_newlines(&buf[..offset]);
+                    self.first_invalid = Some((offset as u64, newlines));
+                }
+                self.invalid_sequences += 1;
+                offset += invalid.len();
+
+                // a single U+FFFD char
+                if needs.chars {
+                    self.chars += 1;
+                }
+                if needs.lengths {
+                    self.tail += 1;
+                    self.last_cr = false;
+                }
+            }
+        }
+    }
+
+    // same as push_lines(), but lengths are in bytes. When they're not needed, the last line
+    // is only known to be empty or not
+    fn byte_lines(&mut self, buf: &[u8]) {
+        match (
+            buf.iter().position(|&b| b == b'\n'),
+            buf.iter().rposition(|&b| b == b'\n'),
+        ) {
+            (Some(first), Some(last)) => {
+                self.head = first as u64;
+                self.tail = (buf.len() - last - 1) as u64;
+            }
+            _ => self.tail = buf.len() as u64,
+        }
+    }
+
+    // add text, keeping track of the lengths of its lines
+    fn push_lines(&mut self, text: &str) {
+        let bytes = text.as_bytes();
+
+        // chars are bytes in ASCII text
+        let ascii = text.is_ascii();
+        let chars = |line: &[u8]| match ascii {
+            true => line.len() as u64,
+            false => scan::count_chars(line),
+        };
+
+        let mut start = 0;
+        scan::for_each_newline(bytes, |end| {
+            let line = &bytes[start..end];
+            if let Some(&last) = line.last() {
+                self.tail += chars(line);
+                self.last_cr = last == b'\r';
+            }
+
+            // the line before is complete, unless it started before this data
+            if self.newlines > 0 {
+                self.line(line_length(self.tail, self.last_cr));
+            } else {
+                self.head = self.tail;
+                self.head_cr = self.last_cr;
+            }
+            self.newlines += 1;
+            self.tail = 0;
+            self.last_cr = false;
+            start = end + 1;
+        });
+
+        let line = &bytes[start..];
+        if let Some(&last) = line.last() {
+            self.tail += chars(line);
+            self.last_cr = last == b'\r';
+        }
+    }
+
+    fn line(&mut self, length: u64) {
+        self.max_line = self.max_line.max(length);
+        self.min_line = self.min_line.min(length);
+    }
+
+    // merge with the counts of the data immediately following
+    fn merge(mut self, other: Body) -> Body {
+        if other.bytes == 0 {
+            return self;
+        }
+        if self.bytes == 0 {
+            return other;
+        }
+
+        // a word split in two is counted once
+        let split_word = !self.last_blank && !other.first_blank;
+
+        if self.first_invalid.is_none() {
+            self.first_invalid = other
+                .first_invalid
+                .map(|(offset, newlines)| (offset + self.bytes, newlines + self.newlines));
+        }
+
+        // the carriage return before the first newline of the other data might be here
+        let joined_cr = if other.head > 0 {
+            other.head_cr
+        } else {
+            self.last_cr
+        };
+
+        match (self.newlines, other.newlines) {
+            (_, 0) => self.tail += other.tail,
+            (0, _) => {
+                self.head = self.tail + other.head;
+                self.head_cr = joined_cr;
+                self.tail = other.tail;
+            }
+            _ => {
+                self.line(line_length(self.tail + other.head, joined_cr));
+                self.tail = other.tail;
+            }
+        }
+
+        self.max_line = self.max_line.max(other.max_line);
+        self.min_line = self.min_line.min(other.min_line);
+
+        self.bytes += other.bytes;
+        self.chars += other.chars;
+        self.words += other.words - u64::from(split_word);
+        self.newlines += other.newlines;
+        self.invalid_sequences += other.invalid_sequences;
+        self.last_blank = other.last_blank;
+        self.last_cr = other.last_cr;
+
+        self
+    }
+}
+
+// length of a line, given its number of chars before the newline and whether the last of them
+// is a carriage return
+#[cfg(target_family = "unix")]
+fn line_length(chars: u64, _cr: bool) -> u64 {
+    chars
+}
+
+#[cfg(target_family = "windows")]
+fn line_length(chars: u64, cr: bool) -> u64 {
+    match (chars, cr) {
+        // a lone newline is counted as a 1 char line
+        (0, false) => 1,
+        (_, true) => chars - 1,
+        _ => chars,
+    }
+}
+
+fn is_continuation(b: u8) -> bool {
+    b & 0xC0 == 0x80
+}
+
+// number of continuation bytes at the beginning, which could belong to a previous sequence
+fn lead_length(buf: &[u8]) -> usize {
+    buf.iter()
+        .take(3)
+        .take_while(|&&b| is_continuation(b))
+        .count()
+}
+
+// position of the incomplete sequence at the end of the buffer, or its length if there's none
+fn trail_start(buf: &[u8]) -> usize {
+    let start = buf.len().saturating_sub(3);
+
+    if let Some(i) = buf[start..].iter().rposition(|&b| !is_continuation(b)) {
+        let i = start + i;
+        let expected = match buf[i] {
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => 0,
+        };
+
+        if buf.len() - i < expected {
+            return i;
+        }
+    }
+
+    buf.len()
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+    use super::*;
+    use crate::metric::Builtin;
+
+    // some multi-byte chars, words and lines, invalid and incomplete sequences
+    const SAMPLES: &[&[u8]] = &[
+        b"",
+        b"\n",
+        b"one",
+        b"  two words \n\nthree\tmore words\n",
+        "αβγ δ\n€uro 😀 \u{a0}x\nlast line".as_bytes(),
+        b"dos\r\nline\r\n\r\n\r",
+        b"bad \xE2\x82 seq\n\x80\x80\x80\x80 cont \xF0\x9F\x98\n\xC0\xAF end \xE2",
+        "a\u{a0}b\u{3000}\u{2003}c\u{85}\nd\u{2029}".as_bytes(),
+        b"x\xC2 \xE2\x80\xE3\x80\x80y\xE2\x80\x8B",
+    ];
+
+    // count line by line, the straightforward way
+    fn expected(whole: &[u8], options: &Config) -> Result<Stats, Error> {
+        let mut stats = Stats::default();
+        stats.min_line = if whole.is_empty() { 0 } else { u64::MAX };
+
+        let mut offset = 0;
+        for (i, line) in whole.split_inclusive(|&b| b == b'\n').enumerate() {
+            // words are the same whether data is decoded or not
+            if !options.decode_utf8() {
+                stats.words += String::from_utf8_lossy(line).split_whitespace().count() as u64;
+                stats.lines += 1;
+                continue;
+            }
+
+            if let Err(e) = std::str::from_utf8(line) {
+                if options.invalid == InvalidMode::Strict {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "invalid UTF-8 sequence at byte offset {}, line {}",
+                            offset + e.valid_up_to(),
+                            i + 1
+                        ),
+                    ));
+                }
+            }
+            offset += line.len();
+
+            stats.invalid_sequences += line
+                .utf8_chunks()
+                .filter(|chunk| !chunk.invalid().is_empty())
+                .count() as u64;
+
+            let line = String::from_utf8_lossy(line);
+            let length = line.trim_end_matches('\n').chars().count() as u64;
+
+            stats.bytes += line.len() as u64;
+            stats.chars += line.chars().count() as u64;
+            stats.words += line.split_whitespace().count() as u64;
+            stats.lines += 1;
+            stats.max_line = stats.max_line.max(length);
+            stats.min_line = stats.min_line.min(length);
+        }
+
+        // bytes are the ones before replacing invalid sequences
+        if options.bytes {
+            stats.bytes = whole.len() as u64;
+        }
+        if !options.decode_utf8() {
+            stats.min_line = 0;
+        }
+
+        Ok(stats)
+    }
+
+    fn check(whole: &[u8], chunks: &[&[u8]], options: &Config) {
+        let expected = expected(whole, options);
+
+        let mut merged = ChunkStats::new(&[], options);
+        for chunk in chunks {
+            merged = merged.merge(ChunkStats::new(chunk, options));
+        }
+        let stats = merged.finish(options);
+
+        match (expected, stats) {
+            (Ok(expected), Ok(stats)) => {
+                let context = format!("{:?} split as {:?}", whole, chunks);
+                assert_eq!(stats.bytes, expected.bytes, "{}", context);
+                assert_eq!(stats.chars, expected.chars, "{}", context);
+                assert_eq!(stats.words, expected.words, "{}", context);
+                assert_eq!(stats.lines, expected.lines, "{}", context);
+                assert_eq!(stats.max_line, expected.max_line, "{}", context);
+                assert_eq!(stats.min_line, expected.min_line, "{}", context);
+                assert_eq!(
+                    stats.invalid_sequences, expected.invalid_sequences,
+                    "{}",
+                    context
+                );
+            }
+            (Err(expected), Err(e)) => assert_eq!(e.to_string(), expected.to_string()),
+            (expected, stats) => panic!("{:?} != {:?}", stats, expected),
+        }
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn merge() {
+        let mut all = Config::default();
+        all.bytes = true;
+        all.chars = true;
+        all.words = true;
+        all.lines = true;
+        all.max_line = true;
+        all.min_line = true;
+
+        let mut lossy = all.clone();
+        lossy.invalid = InvalidMode::Lossy;
+
+        let mut bytes = Config::default();
+        bytes.lines = true;
+        bytes.words = true;
+
+        for options in &[all, lossy, bytes] {
+            for sample in SAMPLES {
+                check(sample, &[sample], options);
+
+                // split anywhere in 2 or 3 chunks
+                for i in 0..=sample.len() {
+                    check(sample, &[&sample[..i], &sample[i..]], options);
+
+                    for j in i..=sample.len() {
+                        check(
+                            sample,
+                            &[&sample[..i], &sample[i..j], &sample[j..]],
+                            options,
+                        );
+                    }
+                }
+
+                // or byte by byte
+                let bytes: Vec<&[u8]> = sample.chunks(1).collect();
+                check(sample, &bytes, options);
+            }
+        }
+    }
+
+    fn count(chunks: &[&[u8]], options: &Config) -> Stats {
+        let mut merged = ChunkStats::new(&[], options);
+        for chunk in chunks {
+            merged = merged.merge(ChunkStats::new(chunk, options));
+        }
+        merged.finish(options).unwrap()
+    }
+
+    #[test]
+    fn requested_only() {
+        let mut all = Config::all();
+        all.invalid = InvalidMode::Lossy;
+
+        // each count made alone, which skips the work for the other ones
+        let alone: Vec<Config> = (0..Builtin::ALL.len())
+            .map(|i| {
+                let mut options = Config::default();
+                options.invalid = InvalidMode::Lossy;
+                match Builtin::ALL[i] {
+                    Builtin::Bytes => options.bytes = true,
+                    Builtin::Chars => options.chars = true,
+                    Builtin::Words => options.words = true,
+                    Builtin::Lines => options.lines = true,
+                    Builtin::MinLine => options.min_line = true,
+                    Builtin::MaxLine => options.max_line = true,
+                }
+                options
+            })
+            .collect();
+
+        for sample in SAMPLES {
+            for i in 0..=sample.len() {
+                let chunks = [&sample[..i], &sample[i..]];
+                let expected = count(&chunks, &all);
+
+                for (kind, options) in Builtin::ALL.iter().zip(&alone) {
+                    let stats = count(&chunks, options);
+                    assert_eq!(
+                        kind.value(&stats),
+                        kind.value(&expected),
+                        "{:?} of {:?} split at {}",
+                        kind,
+                        sample,
+                        i
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::io::{BufRead, Error, ErrorKind, Read};
+
+use flate2::bufread::{GzDecoder, MultiGzDecoder};
+
+use crate::config::Config;
+
+// number of bytes at the beginning of the input needed to recognize any compression format
+pub const MAGIC_SIZE: usize = 10;
+
+// enough bytes to also hold the gzip header, original file name included
+const GZIP_HEADER_SIZE: usize = 8 * 1024;
+
+/// Compression formats which are transparently decompressed before counting. Apart from gzip,
+/// each of them depends on a cargo feature
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Lzma,
+    Zstd,
+    Lz4,
+}
+
+impl Compression {
+    /// convert a format name as given on the command line
+    pub fn from_name(name: &str) -> Option<Compression> {
+        match name.to_lowercase().as_str() {
+            "gzip" | "gz" => Some(Compression::Gzip),
+            "bzip2" | "bz2" => Some(Compression::Bzip2),
+            "xz" => Some(Compression::Xz),
+            "lzma" => Some(Compression::Lzma),
+            "zstd" | "zst" => Some(Compression::Zstd),
+            "lz4" => Some(Compression::Lz4),
+            _ => None,
+        }
+    }
+
+    /// guess the compression format from the first bytes of the input
+    pub fn from_magic(buf: &[u8]) -> Option<Compression> {
+        if buf.starts_with(&[0x1F, 0x8B]) {
+            Some(Compression::Gzip)
+        } else if is_bzip2(buf) {
+            Some(Compression::Bzip2)
+        } else if buf.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if buf.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Some(Compression::Zstd)
+        } else if buf.starts_with(&[0x04, 0x22, 0x4D, 0x18]) {
+            Some(Compression::Lz4)
+        // legacy .lzma files have no real magic, but the header almost always starts with
+        // the default properties followed by a small dictionary size
+        } else if buf.starts_with(&[0x5D, 0x00, 0x00]) {
+            Some(Compression::Lzma)
+        } else {
+            None
+        }
+    }
+
+    /// compression is guessed from the magic bytes, unless forced or disabled
+    pub fn select(head: &[u8], options: &Config) -> Option<Compression> {
+        if options.no_decompress {
+            None
+        } else if options.decompress.is_some() {
+            options.decompress
+        } else if options.zipped {
+            Some(Compression::Gzip)
+        } else {
+            Compression::from_magic(head)
+        }
+    }
+
+    // number of bytes at the beginning of the input needed by select(), and by
+    // GzipHeader::from_head() if the header is requested
+    pub(crate) fn head_size(options: &Config) -> usize {
+        if options.gzip_header {
+            GZIP_HEADER_SIZE
+        } else {
+            MAGIC_SIZE
+        }
+    }
+
+    /// wrap the reader into the right decoder, which can be moved to another thread
+    pub fn decoder<'a, R: BufRead + Send + 'a>(
+        &self,
+        reader: R,
+    ) -> Result<Box<dyn Read + Send + 'a>, Error> {
+        match self {
+            // concatenated members, as in BGZF files, are all decoded
+            Compression::Gzip => Ok(Box::new(MultiGzDecoder::new(reader))),
+
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => Ok(Box::new(bzip2::bufread::MultiBzDecoder::new(reader))),
+
+            #[cfg(feature = "xz")]
+            Compression::Xz => Ok(Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader))),
+
+            #[cfg(feature = "xz")]
+            Compression::Lzma => {
+                let stream = xz2::stream::Stream::new_lzma_decoder(u64::MAX)?;
+                Ok(Box::new(xz2::bufread::XzDecoder::new_stream(
+                    reader, stream,
+                )))
+            }
+
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::with_buffer(reader)?)),
+
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(Box::new(lz4_flex::frame::FrameDecoder::new(reader))),
+
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("awc was built without {:?} support", self),
+            )),
+        }
+    }
+}
+
+// "BZh" is too common at the beginning of a text, so it must be followed by the block size and
+// the magic of the first block, or of the end of stream for empty data
+fn is_bzip2(buf: &[u8]) -> bool {
+    const BLOCK: [u8; 6] = [0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+    const END: [u8; 6] = [0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+
+    buf.len() >= MAGIC_SIZE
+        && buf.starts_with(b"BZh")
+        && (b'1'..=b'9').contains(&buf[3])
+        && (buf[4..MAGIC_SIZE] == BLOCK || buf[4..MAGIC_SIZE] == END)
+}
+
+/// Metadata stored in the header of the first gzip member
+#[derive(Debug, PartialEq)]
+pub struct GzipHeader {
+    /// original file name, if any
+    pub name: Option<String>,
+
+    /// modification time of the original file, in seconds since the epoch (0 if not set)
+    pub mtime: u32,
+}
+
+impl GzipHeader {
+    /// parse the header from the first bytes of the file
+    pub fn from_head(head: &[u8]) -> Option<GzipHeader> {
+        let decoder = GzDecoder::new(head);
+        let header = decoder.header()?;
+
+        Some(GzipHeader {
+            // file name is ISO 8859-1 encoded
+            name: header
+                .filename()
+                .map(|name| name.iter().map(|&b| b as char).collect()),
+            mtime: header.mtime(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_magic() {
+        assert_eq!(
+            Compression::from_magic(&[0x1F, 0x8B, 0x08, 0x08]),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_magic(b"BZh91AY&SY"),
+            Some(Compression::Bzip2)
+        );
+        assert_eq!(
+            Compression::from_magic(&[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00, 0x00]),
+            Some(Compression::Xz)
+        );
+        assert_eq!(
+            Compression::from_magic(&[0x5D, 0x00, 0x00, 0x80, 0x00]),
+            Some(Compression::Lzma)
+        );
+        assert_eq!(
+            Compression::from_magic(&[0x28, 0xB5, 0x2F, 0xFD, 0x24]),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(
+            Compression::from_magic(&[0x04, 0x22, 0x4D, 0x18, 0x64]),
+            Some(Compression::Lz4)
+        );
+        assert_eq!(Compression::from_magic(&[0x1F]), None);
+        assert_eq!(Compression::from_magic(b"plain text"), None);
+
+        // text which happens to start like bzip2 data
+        assert_eq!(Compression::from_magic(b"BZh is a word\n"), None);
+        assert_eq!(Compression::from_magic(b"BZh9 is a word\n"), None);
+        assert_eq!(Compression::from_magic(b"BZh91AY&S"), None);
+        assert_eq!(
+            Compression::from_magic(&[b'B', b'Z', b'h', b'9', 0x17, 0x72, 0x45, 0x38, 0x50, 0x90]),
+            Some(Compression::Bzip2)
+        );
+
+        let head = std::fs::read("tests/poe.unix.bz2").unwrap();
+        assert_eq!(Compression::from_magic(&head), Some(Compression::Bzip2));
+    }
+
+    #[test]
+    fn text_starting_with_bzh() {
+        let mut options = Config::all();
+        options.jobs = 1;
+        let stats = crate::counter::Counter::count("tests/bzh.txt", &options).unwrap();
+
+        assert_eq!(stats.words, 4);
+        assert_eq!(stats.lines, 1);
+    }
+
+    #[test]
+    fn gzip_header() {
+        let head = std::fs::read("tests/poe.unix.gz").unwrap();
+        assert_eq!(
+            GzipHeader::from_head(&head),
+            Some(GzipHeader {
+                name: Some("poe.unix".to_string()),
+                mtime: 1613844312
+            })
+        );
+        assert_eq!(GzipHeader::from_head(b"plain text"), None);
+    }
+}
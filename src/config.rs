@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use crate::compression::Compression;
+use crate::decode::TextEncoding;
+use crate::metric::{Builtin, Metric};
+
+/// What to do when an invalid UTF-8 sequence is met
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum InvalidMode {
+    /// stop counting the file with an error
+    #[default]
+    Strict,
+
+    /// replace invalid sequences by U+FFFD
+    Lossy,
+
+    /// same as lossy, but also output the number of invalid sequences
+    Report,
+}
+
+/// What to count and how to read the input. Counts which are not requested are left to 0 in
+/// the resulting [`Stats`](crate::Stats). New fields might be added, so it's built from
+/// [`Config::default`] or [`Config::all`] and then changed field by field.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Config {
+    /// count the bytes
+    pub bytes: bool,
+
+    /// count the chars
+    pub chars: bool,
+
+    /// count the words (blank-separated)
+    pub words: bool,
+
+    /// count the lines
+    pub lines: bool,
+
+    /// get the length of the longest line
+    pub max_line: bool,
+
+    /// get the length of the shortest line
+    pub min_line: bool,
+
+    /// whether file is zipped, whatever its first bytes
+    pub zipped: bool,
+
+    /// never decompress files
+    pub no_decompress: bool,
+
+    /// compression format of the files, whatever their first bytes. When counting
+    /// asynchronously, only gzip is decompressed: any other format gives an
+    /// [`ErrorKind::Unsupported`](std::io::ErrorKind::Unsupported) error
+    pub decompress: Option<Compression>,
+
+    /// count bytes even if not requested, to get the compression ratio
+    pub ratio: bool,
+
+    /// get the original name and modification time stored in the gzip header
+    pub gzip_header: bool,
+
+    /// keep the counts made before a read error, e.g.: in a truncated compressed file
+    pub salvage: bool,
+
+    /// number of threads used to count, 0 meaning the number of CPUs
+    pub jobs: usize,
+
+    /// map regular files in memory instead of reading them
+    pub mmap: bool,
+
+    /// encoding of the input files when there's no BOM
+    pub encoding: Option<TextEncoding>,
+
+    /// guess the encoding of each input file without a BOM
+    pub detect_encoding: bool,
+
+    /// how to deal with invalid UTF-8
+    pub invalid: InvalidMode,
+
+    /// extra counts, made along with the built-in ones and printed after them
+    pub metrics: Vec<Arc<dyn Metric>>,
+}
+
+impl Config {
+    /// Same as the default configuration, but with all counts requested.
+    pub fn all() -> Config {
+        Config {
+            bytes: true,
+            chars: true,
+            words: true,
+            lines: true,
+            max_line: true,
+            min_line: true,
+            ..Config::default()
+        }
+    }
+
+    /// Number of threads used to count, resolving 0 to the number of CPUs.
+    pub fn jobs(&self) -> usize {
+        if self.jobs == 0 {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        } else {
+            self.jobs
+        }
+    }
+
+    // true if data needs to be decoded as UTF-8 to get the requested counts
+    pub(crate) fn decode_utf8(&self) -> bool {
+        self.chars || self.max_line || self.min_line || self.invalid == InvalidMode::Report
+    }
+
+    /// True if at least one count is requested.
+    pub fn any_count(&self) -> bool {
+        Builtin::ALL.iter().any(|b| b.requested(self)) || !self.metrics.is_empty()
+    }
+}
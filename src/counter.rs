@@ -1,163 +1,710 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error};
+use std::io::{self, BufRead, BufReader, Cursor, Error, ErrorKind, Read};
 
 use std::path::Path;
 
-use flate2::read::GzDecoder;
-
-use crate::options::CliOptions;
+use crate::chunk::ChunkStats;
+use crate::compression::{Compression, GzipHeader, MAGIC_SIZE};
+use crate::config::{Config, InvalidMode};
+use crate::decode::{TextEncoding, Transcoder};
+use crate::pipeline::PipeReader;
 use crate::stats::Stats;
+use crate::streaming::StreamingCounter;
+
+// enough bytes to hold any BOM
+const BOM_SIZE: usize = 4;
+
+// number of bytes at the beginning of a file used to guess its encoding
+const DETECTION_SIZE: usize = 64 * 1024;
+
+// files are split into ranges counted in parallel only if each range gets at least this size
+const MIN_RANGE_SIZE: u64 = 4 * 1024 * 1024;
 
+// size of the blocks read from each range
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Counts files or readers, as requested by a [`Config`].
 pub struct Counter;
 
 impl Counter {
-    pub fn count<P: AsRef<Path>>(file_name: P, options: &CliOptions) -> Result<Stats, Error> {
-        let file = File::open(file_name)?;
+    /// count a file, "-" being the standard input. Unless disabled in the configuration,
+    /// compressed files are transparently decompressed
+    pub fn count<P: AsRef<Path>>(file_name: P, options: &Config) -> Result<Stats, Error> {
+        // the size of a regular file is enough if nothing else is needed
+        if let Some(stats) = Counter::count_from_metadata(file_name.as_ref(), options)? {
+            return Ok(stats);
+        }
 
-        let stats = if options.zipped {
-            let decoder = GzDecoder::new(file);
-            let reader = BufReader::new(decoder);
-            Counter::read_file(reader, &options)?
-        } else {
-            let reader = BufReader::new(file);
-            Counter::read_file(reader, &options)?
+        // regular files might be mapped in memory, or split and counted by several threads
+        #[cfg(target_family = "unix")]
+        if let Some(stats) = Counter::count_plain(file_name.as_ref(), options)? {
+            return Ok(stats);
+        }
+
+        Counter::count_stream(open(file_name)?, options)
+    }
+
+    // count data which might be compressed, read as a stream
+    fn count_stream<R: Read + Send>(reader: R, options: &Config) -> Result<Stats, Error> {
+        // keep track of the bytes read, before decompression
+        let mut file = ByteCounter::new(reader);
+
+        let mut header = None;
+
+        let mut stats = {
+            // the head is put back in front of the remaining data
+            let head = read_head(&mut file, Compression::head_size(options))?;
+            let compression = Compression::select(&head, options);
+
+            if options.gzip_header && compression == Some(Compression::Gzip) {
+                header = GzipHeader::from_head(&head);
+            }
+
+            let reader = BufReader::new(Cursor::new(head).chain(&mut file));
+
+            match compression {
+                // decompression runs on its own thread, while data is counted on this one
+                Some(compression) => {
+                    let decoder = compression.decoder(reader)?;
+                    std::thread::scope(|scope| {
+                        Counter::count_reader(PipeReader::spawn(scope, decoder), options)
+                    })?
+                }
+                None => Counter::count_reader(reader, options)?,
+            }
         };
 
+        stats.compressed_bytes = file.bytes;
+
+        if let Some(header) = header {
+            stats.original_name = header.name;
+            stats.mtime = Some(header.mtime);
+        }
+
         Ok(stats)
     }
 
-    // calculate and return statistics. Need to buld this function with
-    // T: BufRead to read either regular or compressed files
-    pub fn read_file<T: BufRead>(mut reader: T, opt: &CliOptions) -> Result<Stats, Error> {
-        // used to acculmate and keep statistics
-        let mut stats = Stats::default();
+    // when only bytes are requested, get them from the metadata of uncompressed regular files.
+    // Files reporting a 0 size (e.g.: in /proc) still need to be read
+    fn count_from_metadata(path: &Path, options: &Config) -> Result<Option<Stats>, Error> {
+        let bytes_only = !(options.chars
+            || options.words
+            || options.lines
+            || options.max_line
+            || options.min_line);
 
-        // initialize value for calculating minimum length
-        if opt.min_line {
-            stats.min_line = std::u64::MAX;
+        if !bytes_only
+            || !options.metrics.is_empty()
+            || path == Path::new("-")
+            || options.detect_encoding
+            || options.invalid == InvalidMode::Report
+        {
+            return Ok(None);
         }
 
-        // this buffer will receive each line
-        let mut line = String::with_capacity(512);
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || metadata.len() == 0 {
+            return Ok(None);
+        }
 
-        loop {
-            // read next line
-            let nb_read = match reader.read_line(&mut line) {
-                Ok(n) => n,
-                Err(e) => return Err(e),
-            };
+        // compressed files need to be decompressed to get their size
+        if !options.no_decompress {
+            let mut head = Vec::with_capacity(MAGIC_SIZE);
+            Read::by_ref(&mut file)
+                .take(MAGIC_SIZE as u64)
+                .read_to_end(&mut head)?;
 
-            // did we meet EOF?
-            if nb_read == 0 {
-                break;
+            if Compression::select(&head, options).is_some() {
+                return Ok(None);
             }
+        }
 
-            // one more line
-            if opt.lines {
-                stats.lines += 1;
-            }
+        let mut stats = Stats::default();
+        if options.bytes || options.ratio {
+            stats.bytes = metadata.len();
+        }
+        stats.compressed_bytes = metadata.len();
 
-            // count bytes if any
-            if opt.bytes {
-                stats.bytes += nb_read as u64;
-            }
+        Ok(Some(stats))
+    }
 
-            // count chars if any
-            if opt.chars {
-                stats.chars += line.chars().count() as u64;
-                //eprint!("{};{};{};{}", nb_read, line.chars().count(), nb_read-line.chars().count(), line);
-            }
+    // count a regular uncompressed UTF-8 file without the streaming readers: either memory-mapped
+    // with --mmap, or split into ranges read by options.jobs() threads if it's large enough.
+    // Otherwise, None is returned and the file is counted sequentially
+    #[cfg(target_family = "unix")]
+    fn count_plain(path: &Path, options: &Config) -> Result<Option<Stats>, Error> {
+        use std::os::unix::fs::FileExt;
 
-            // count chars if any
-            if opt.words {
-                stats.words += line.split_whitespace().count() as u64;
-            }
+        let jobs = options.jobs() as u64;
+
+        // encodings other than UTF-8 need to be transcoded from the beginning
+        let utf8 = matches!(options.encoding, None | Some(TextEncoding::Utf8));
+        if (jobs < 2 && !options.mmap)
+            || path == Path::new("-")
+            || !utf8
+            || options.detect_encoding
+            || options.salvage
+            || !options.metrics.is_empty()
+        {
+            return Ok(None);
+        }
+
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+
+        let ranges = jobs.min(metadata.len() / MIN_RANGE_SIZE).max(1);
+        if !metadata.is_file() || (ranges < 2 && !options.mmap) {
+            return Ok(None);
+        }
+
+        let mut head = [0u8; MAGIC_SIZE];
+        let length = file.read_at(&mut head, 0)?;
+        let head = &head[..length];
+
+        if Compression::select(head, options).is_some() {
+            return Ok(None);
+        }
+
+        // like in count_reader(), a BOM takes precedence over the requested encoding
+        let start = match TextEncoding::from_bom(head) {
+            Some((TextEncoding::Utf8, length)) => length as u64,
+            Some(_) => return Ok(None),
+            None => 0,
+        };
+
+        let stats = if options.mmap {
+            Counter::count_mapped(&file, start, ranges, options)
+        } else {
+            Some(Counter::count_ranges(
+                &file,
+                start,
+                metadata.len(),
+                ranges,
+                options,
+            ))
+        };
+
+        let mut stats = match stats {
+            Some(stats) => stats?,
+            None => return Ok(None),
+        };
+
+        if options.bytes || options.ratio {
+            stats.bytes = metadata.len();
+        }
+        stats.compressed_bytes = metadata.len();
+
+        Ok(Some(stats))
+    }
+
+    // map the file in memory and count ranges of it in parallel. None is returned if the file
+    // can't be mapped
+    #[cfg(target_family = "unix")]
+    fn count_mapped(
+        file: &File,
+        start: u64,
+        ranges: u64,
+        options: &Config,
+    ) -> Option<Result<Stats, Error>> {
+        // the file is not expected to be modified while it's counted
+        let map = unsafe { memmap2::Mmap::map(file) }.ok()?;
+        let data = &map[(start as usize).min(map.len())..];
 
-            // calculate max_line if any
-            if opt.max_line {
-                let tmp = line_length(&line);
+        let range_size = data.len().div_ceil(ranges as usize).max(1);
 
-                if tmp > stats.max_line {
-                    stats.max_line = tmp;
+        let chunks: Vec<ChunkStats> = std::thread::scope(|scope| {
+            let handles: Vec<_> = data
+                .chunks(range_size)
+                .map(|range| scope.spawn(move || ChunkStats::new(range, options)))
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("counting thread panicked"))
+                .collect()
+        });
+
+        let mut merged = ChunkStats::new(&[], options);
+        for chunk in chunks {
+            merged = merged.merge(chunk);
+        }
+
+        Some(merged.finish(options))
+    }
+
+    // split the [start, end) part of the file into ranges, each one counted by its own thread,
+    // and merge the results
+    #[cfg(target_family = "unix")]
+    fn count_ranges(
+        file: &File,
+        start: u64,
+        end: u64,
+        ranges: u64,
+        options: &Config,
+    ) -> Result<Stats, Error> {
+        use std::os::unix::fs::FileExt;
+
+        let range_size = (end - start).div_ceil(ranges);
+
+        let chunks: Vec<Result<ChunkStats, Error>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..ranges)
+                .map(|i| {
+                    let from = (start + i * range_size).min(end);
+                    let to = (from + range_size).min(end);
+
+                    scope.spawn(move || {
+                        let mut chunk = ChunkStats::new(&[], options);
+                        let mut buffer = vec![0u8; BLOCK_SIZE];
+                        let mut offset = from;
+
+                        while offset < to {
+                            let length = ((to - offset) as usize).min(BLOCK_SIZE);
+                            let n = file.read_at(&mut buffer[..length], offset)?;
+
+                            // file was truncated in the meantime
+                            if n == 0 {
+                                break;
+                            }
+
+                            chunk = chunk.merge(ChunkStats::new(&buffer[..n], options));
+                            offset += n as u64;
+                        }
+
+                        Ok(chunk)
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("counting thread panicked"))
+                .collect()
+        });
+
+        let mut merged = ChunkStats::new(&[], options);
+        for chunk in chunks {
+            merged = merged.merge(chunk?);
+        }
+
+        merged.finish(options)
+    }
+
+    /// sniff the BOM, guess or use the requested encoding, and transcode data to UTF-8 if necessary
+    /// before counting. Bytes are always the ones read from the reader, not the transcoded ones
+    pub fn count_reader<R: Read>(reader: R, options: &Config) -> Result<Stats, Error> {
+        let mut raw = ByteCounter::new(reader);
+
+        // the beginning of the input is kept aside to look for a BOM or to guess the encoding
+        let mut head = Vec::with_capacity(Counter::head_size(options));
+        let result = Read::by_ref(&mut raw)
+            .take(head.capacity() as u64)
+            .read_to_end(&mut head);
+        let mut input = Input::new(&head, result, options)?;
+
+        if input.read_more() {
+            let mut reader = BufReader::new(&mut raw);
+
+            loop {
+                let buffer = match reader.fill_buf() {
+                    Ok(buffer) => buffer,
+                    Err(e) => match input.retry(e)? {
+                        true => continue,
+                        false => break,
+                    },
+                };
+
+                // did we meet EOF?
+                if buffer.is_empty() {
+                    break;
                 }
-            }
 
-            // calculate min_line if any
-            if opt.min_line {
-                let tmp = line_length(&line);
+                let length = buffer.len();
+                let more = input.update(buffer)?;
+                reader.consume(length);
 
-                if tmp < stats.min_line {
-                    stats.min_line = tmp;
+                // no need to read further, the file is in error
+                if !more {
+                    break;
                 }
             }
+        }
 
-            // clear buffer to not accumulate data
-            line.clear();
+        input.finish(raw.bytes, options)
+    }
+
+    // number of bytes at the beginning of the input needed to select its encoding
+    pub(crate) fn head_size(options: &Config) -> usize {
+        if options.detect_encoding {
+            DETECTION_SIZE
+        } else {
+            BOM_SIZE
         }
+    }
 
-        // sanity check for empty files
-        if stats.min_line == std::u64::MAX {
-            stats.min_line = 0;
-        }        
+    /// calculate and return statistics. Need to buld this function with
+    /// T: BufRead to read either regular or compressed files. Data is counted one buffer at a
+    /// time, so memory use doesn't depend on the length of lines
+    pub fn read_file<T: BufRead>(mut reader: T, opt: &Config) -> Result<Stats, Error> {
+        let mut state = ReadState::new(opt);
 
-        Ok(stats)
+        loop {
+            let buffer = match reader.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(e) => match state.retry(e)? {
+                    true => continue,
+                    false => break,
+                },
+            };
+
+            // did we meet EOF?
+            if buffer.is_empty() {
+                break;
+            }
+
+            let length = buffer.len();
+            let more = state.update(buffer);
+            reader.consume(length);
+
+            // no need to read further, the file is in error
+            if !more {
+                break;
+            }
+        }
+
+        state.finish()
     }
 }
 
-// calculate line length
-#[cfg(target_family = "unix")]
-fn line_length(line: &str) -> u64 {
-    // check last char
-    if line.is_empty() {
-        return 0;
+// What read_file() and Input keep track of while reading, so they count the same way
+struct ReadState {
+    counter: StreamingCounter,
+    salvage: bool,
+
+    // number of bytes read so far, to report where a failed reader stopped
+    offset: u64,
+    partial: Option<u64>,
+}
+
+impl ReadState {
+    pub fn new(opt: &Config) -> ReadState {
+        ReadState {
+            counter: StreamingCounter::new(opt),
+            salvage: opt.salvage,
+            offset: 0,
+            partial: None,
+        }
     }
 
-    // other calculate length
-    let l = line.chars().count() as u64;
-    let last_char = line.chars().last().unwrap();
+    // true if reading can be retried after this error. When salvaging, a read error is like
+    // EOF, otherwise it's returned
+    pub fn retry(&mut self, e: Error) -> Result<bool, Error> {
+        if e.kind() == ErrorKind::Interrupted {
+            Ok(true)
+        } else if self.salvage {
+            self.partial = Some(self.offset);
+            Ok(false)
+        } else {
+            Err(e)
+        }
+    }
 
-    if last_char == '\n' {
-        return l - 1;
-    } else {
-        return l;
+    // count the next buffer. Returns false if there's no need to read further
+    pub fn update(&mut self, buffer: &[u8]) -> bool {
+        self.counter.update(buffer);
+        self.offset += buffer.len() as u64;
+        !self.counter.failed()
+    }
+
+    pub fn finish(self) -> Result<Stats, Error> {
+        let mut stats = self.counter.finish()?;
+        stats.partial = self.partial;
+        Ok(stats)
     }
 }
 
-#[cfg(target_family = "windows")]
-fn line_length(line: &str) -> u64 {
-    // check last char
-    if line.is_empty() {
-        return 0;
+// What count_reader() and its async twin keep track of once the head of the input is read: the
+// encoding selected from it, and the counting of the data transcoded to UTF-8. Callers only
+// read the input, so both count the same way
+pub(crate) struct Input {
+    state: ReadState,
+    encoding: TextEncoding,
+    transcoder: Option<Transcoder>,
+    decoded: Vec<u8>,
+
+    // the head couldn't be entirely read, or the data is in error
+    done: bool,
+    head_failed: bool,
+}
+
+impl Input {
+    // select the encoding from the head of the input, given with the result of reading it, and
+    // count it. When salvaging, what was read before an error is counted
+    pub fn new(head: &[u8], result: Result<usize, Error>, opt: &Config) -> Result<Input, Error> {
+        let head_failed = match result {
+            Ok(_) => false,
+            Err(_) if opt.salvage => true,
+            Err(e) => return Err(e),
+        };
+
+        let last = head_failed || head.len() < Counter::head_size(opt);
+        let (encoding, bom_length) = TextEncoding::select(head, last, opt);
+
+        let mut input = Input {
+            state: ReadState::new(opt),
+            encoding,
+            transcoder: match encoding {
+                TextEncoding::Utf8 => None,
+                _ => Some(Transcoder::new(encoding)),
+            },
+            decoded: Vec::new(),
+            done: head_failed,
+            head_failed,
+        };
+
+        // don't read anymore from a failed reader
+        let more = input.update(&head[bom_length..])?;
+        input.done |= !more;
+
+        Ok(input)
     }
 
-    // other calculate length
-    let l = line.chars().count();
-    if l == 1 {
-        return 1;
+    // true if the rest of the input needs to be read
+    pub fn read_more(&self) -> bool {
+        !self.done
     }
 
-    let last_char = line.chars().last().unwrap();
-    let before_last_char = line.chars().skip(l - 2).next();
+    // same as ReadState::retry()
+    pub fn retry(&mut self, e: Error) -> Result<bool, Error> {
+        self.state.retry(e)
+    }
 
-    if last_char == '\n' {
-        if before_last_char.unwrap() == '\r' {
-            return (l - 2) as u64;
-        } else {
-            return (l - 1) as u64;
+    // transcode and count the next buffer. Returns false if there's no need to read further
+    pub fn update(&mut self, buffer: &[u8]) -> Result<bool, Error> {
+        self.count(buffer, false)
+    }
+
+    // complete the statistics, given the number of bytes read from the input
+    pub fn finish(mut self, raw_bytes: u64, opt: &Config) -> Result<Stats, Error> {
+        // flush what's left in the transcoder
+        self.count(&[], true)?;
+        let mut stats = self.state.finish()?;
+
+        if opt.detect_encoding {
+            stats.encoding = Some(self.encoding.name());
+        }
+
+        // report the offset in the data read, before any transcoding
+        if self.head_failed || stats.partial.is_some() {
+            stats.partial = Some(raw_bytes);
         }
+
+        if opt.bytes || opt.ratio {
+            stats.bytes = raw_bytes;
+        }
+
+        // the reader is not supposed to be compressed, unless the caller knows better
+        stats.compressed_bytes = raw_bytes;
+
+        Ok(stats)
+    }
+
+    fn count(&mut self, buffer: &[u8], last: bool) -> Result<bool, Error> {
+        match &mut self.transcoder {
+            Some(transcoder) => {
+                self.decoded.clear();
+                transcoder.transcode(buffer, last, &mut self.decoded)?;
+                Ok(self.state.update(&self.decoded))
+            }
+            None => Ok(self.state.update(buffer)),
+        }
+    }
+}
+
+/// open a file for reading, "-" being the standard input
+pub fn open<P: AsRef<Path>>(file_name: P) -> Result<Box<dyn Read + Send>, Error> {
+    let path = file_name.as_ref();
+
+    if path == Path::new("-") {
+        Ok(Box::new(io::stdin()))
     } else {
-        return l as u64;
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+// read the beginning of the input, up to size bytes. A single read is not enough, as pipes
+// might give less data
+pub fn read_head<R: Read>(reader: &mut R, size: usize) -> Result<Vec<u8>, Error> {
+    let mut head = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+// a reader adaptor which keeps track of the number of bytes read
+pub struct ByteCounter<R> {
+    inner: R,
+    pub bytes: u64,
+}
+
+impl<R: Read> ByteCounter<R> {
+    pub fn new(inner: R) -> ByteCounter<R> {
+        ByteCounter { inner, bytes: 0 }
+    }
+}
+
+impl<R: Read> Read for ByteCounter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let n = self.inner.read(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     use super::*;
 
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_ranges() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.chars = true;
+        options.words = true;
+        options.lines = true;
+        options.max_line = true;
+        options.min_line = true;
+
+        // whatever the number of ranges, counts are the same as when read sequentially
+        for file_name in &["tests/poe.unix", "tests/odysseus.unix", "tests/utf8.txt"] {
+            let expected =
+                Counter::read_file(BufReader::new(File::open(file_name).unwrap()), &options)
+                    .unwrap();
+
+            let file = File::open(file_name).unwrap();
+            let size = file.metadata().unwrap().len();
+
+            for ranges in 1..=16 {
+                let stats = Counter::count_ranges(&file, 0, size, ranges, &options).unwrap();
+
+                assert_eq!(stats.bytes, expected.bytes);
+                assert_eq!(stats.chars, expected.chars);
+                assert_eq!(stats.words, expected.words);
+                assert_eq!(stats.lines, expected.lines);
+                assert_eq!(stats.max_line, expected.max_line);
+                assert_eq!(stats.min_line, expected.min_line);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_mapped() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.chars = true;
+        options.words = true;
+        options.lines = true;
+        options.max_line = true;
+        options.min_line = true;
+        options.jobs = 1;
+
+        for file_name in &["tests/poe.unix", "tests/odysseus.unix", "tests/utf8.txt"] {
+            let expected = Counter::count(file_name, &options).unwrap();
+
+            let file = File::open(file_name).unwrap();
+            for ranges in 1..=4 {
+                let stats = Counter::count_mapped(&file, 0, ranges, &options)
+                    .unwrap()
+                    .unwrap();
+
+                assert_eq!(stats.bytes, expected.bytes);
+                assert_eq!(stats.chars, expected.chars);
+                assert_eq!(stats.words, expected.words);
+                assert_eq!(stats.lines, expected.lines);
+                assert_eq!(stats.max_line, expected.max_line);
+                assert_eq!(stats.min_line, expected.min_line);
+            }
+        }
+
+        // compressed files are not mapped
+        options.mmap = true;
+        let stats = Counter::count("tests/poe.unix.gz", &options).unwrap();
+        assert_eq!(stats.lines, 887);
+    }
+
+    #[test]
+    fn count_from_metadata() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+
+        let stats = Counter::count_from_metadata(Path::new("tests/poe.unix"), &options).unwrap();
+        assert_eq!(stats.unwrap().bytes, 25260);
+
+        // compressed files are read
+        let stats = Counter::count_from_metadata(Path::new("tests/poe.unix.gz"), &options);
+        assert!(stats.unwrap().is_none());
+        assert_eq!(
+            Counter::count("tests/poe.unix.gz", &options).unwrap().bytes,
+            25260
+        );
+
+        // unless they're not decompressed
+        options.no_decompress = true;
+        let stats = Counter::count_from_metadata(Path::new("tests/poe.unix.gz"), &options);
+        assert!(stats.unwrap().is_some());
+
+        // other counts need to read the file
+        options.lines = true;
+        let stats = Counter::count_from_metadata(Path::new("tests/poe.unix"), &options);
+        assert!(stats.unwrap().is_none());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn count_proc_file() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+
+        // size is 0, but the file is not empty
+        let stats = Counter::count_from_metadata(Path::new("/proc/self/status"), &options);
+        assert!(stats.unwrap().is_none());
+        assert!(Counter::count("/proc/self/status", &options).unwrap().bytes > 0);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_long_line() {
+        // set options
+        let mut options = Config::default();
+        options.chars = true;
+        options.words = true;
+        options.lines = true;
+        options.max_line = true;
+        options.min_line = true;
+
+        // a single line much larger than the buffer, never held in memory
+        let size = 16 * 1024 * 1024;
+        let reader = io::repeat(b'a').take(size).chain(&b" \xCE\xB1\nbb"[..]);
+
+        let stats = Counter::read_file(BufReader::new(reader), &options).unwrap();
+
+        assert_eq!(stats.chars, size + 5);
+        assert_eq!(stats.words, 3);
+        assert_eq!(stats.lines, 2);
+        assert_eq!(stats.max_line, size + 2);
+        assert_eq!(stats.min_line, 2);
+    }
+
     #[test]
     fn count_utf8() {
         // set options
-        let mut options = CliOptions::default();
+        let mut options = Config::default();
         options.bytes = true;
         options.chars = true;
         options.words = true;
@@ -179,11 +726,214 @@ mod tests {
         assert_eq!(stats.max_line, 1000);
     }
 
+    #[test]
+    fn count_utf16_utf32() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.chars = true;
+        options.words = true;
+        options.lines = true;
+        options.max_line = true;
+        options.min_line = true;
+
+        // same text as utf8.txt, but with a BOM: bytes are the raw ones, including the BOM
+        for (file, bytes) in &[
+            ("tests/utf16le.txt", 2002),
+            ("tests/utf16be.txt", 2002),
+            ("tests/utf32le.txt", 4004),
+            ("tests/utf32be.txt", 4004),
+        ] {
+            let stats = Counter::count(file, &options);
+            assert!(stats.is_ok());
+
+            let stats = stats.unwrap();
+
+            assert_eq!(stats.bytes, *bytes);
+            assert_eq!(stats.chars, 1000);
+            assert_eq!(stats.words, 3);
+            assert_eq!(stats.lines, 1);
+            assert_eq!(stats.min_line, 1000);
+            assert_eq!(stats.max_line, 1000);
+        }
+
+        // no BOM here, so encoding is given
+        options.encoding = Some(TextEncoding::Utf16Le);
+        let stats = Counter::count("tests/utf16le.nobom.txt", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.bytes, 2000);
+        assert_eq!(stats.chars, 1000);
+        assert_eq!(stats.words, 3);
+        assert_eq!(stats.lines, 1);
+
+        // the encoding is only used for files without a BOM
+        for encoding in &[
+            TextEncoding::Utf8,
+            TextEncoding::from_label("latin1").unwrap(),
+        ] {
+            options.encoding = Some(*encoding);
+            let stats = Counter::count("tests/utf16be.txt", &options).unwrap();
+            assert_eq!(stats.chars, 1000);
+            assert_eq!(stats.words, 3);
+        }
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_legacy() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.chars = true;
+        options.words = true;
+        options.lines = true;
+        options.max_line = true;
+        options.min_line = true;
+
+        // single byte encoding
+        options.encoding = TextEncoding::from_label("windows-1252");
+        let stats = Counter::count("tests/french.windows-1252", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.bytes, 44);
+        assert_eq!(stats.chars, 44);
+        assert_eq!(stats.words, 9);
+        assert_eq!(stats.lines, 3);
+        assert_eq!(stats.max_line, 17);
+        assert_eq!(stats.min_line, 12);
+
+        // multi-byte encoding
+        options.encoding = TextEncoding::from_label("shift_jis");
+        let stats = Counter::count("tests/japanese.shift_jis", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.bytes, 68);
+        assert_eq!(stats.chars, 35);
+        assert_eq!(stats.words, 2);
+        assert_eq!(stats.lines, 2);
+        assert_eq!(stats.max_line, 17);
+        assert_eq!(stats.min_line, 16);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_detected() {
+        // set options
+        let mut options = Config::default();
+        options.chars = true;
+        options.words = true;
+        options.detect_encoding = true;
+
+        let stats = Counter::count("tests/french.windows-1252", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.encoding, Some("windows-1252"));
+        assert_eq!(stats.chars, 44);
+        assert_eq!(stats.words, 9);
+
+        let stats = Counter::count("tests/japanese.shift_jis", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.encoding, Some("Shift_JIS"));
+        assert_eq!(stats.chars, 35);
+        assert_eq!(stats.words, 2);
+
+        // BOM always wins
+        let stats = Counter::count("tests/utf32le.txt", &options);
+        assert!(stats.is_ok());
+        assert_eq!(stats.unwrap().encoding, Some("UTF-32LE"));
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_invalid() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.chars = true;
+        options.words = true;
+        options.lines = true;
+
+        // default is to fail with the position of the first invalid sequence
+        let stats = Counter::count("tests/invalid.txt", &options);
+        assert!(stats.is_err());
+
+        let e = stats.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            e.to_string(),
+            "invalid UTF-8 sequence at byte offset 12, line 2"
+        );
+
+        // each invalid sequence is counted as U+FFFD
+        options.invalid = InvalidMode::Report;
+        let stats = Counter::count("tests/invalid.txt", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.bytes, 32);
+        assert_eq!(stats.chars, 32);
+        assert_eq!(stats.words, 8);
+        assert_eq!(stats.lines, 3);
+        assert_eq!(stats.invalid_sequences, 3);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_bytes_only() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.words = true;
+        options.lines = true;
+
+        // same results as when decoding UTF-8
+        let stats = Counter::count("tests/poe.unix", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.bytes, 25260);
+        assert_eq!(stats.words, 3969);
+        assert_eq!(stats.lines, 887);
+
+        // invalid UTF-8 doesn't matter here
+        let stats = Counter::count("tests/invalid.txt", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.bytes, 32);
+        assert_eq!(stats.words, 8);
+        assert_eq!(stats.lines, 3);
+
+        // no ending newline
+        let stats = Counter::count("tests/utf8.txt", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.bytes, 1904);
+        assert_eq!(stats.lines, 1);
+    }
+
     #[test]
     #[cfg(target_family = "unix")]
     fn count() {
         // set options
-        let mut options = CliOptions::default();
+        let mut options = Config::default();
         options.bytes = true;
         options.chars = true;
         options.words = true;
@@ -221,7 +971,7 @@ mod tests {
     #[cfg(target_family = "unix")]
     fn count_gzipped() {
         // set options
-        let mut options = CliOptions::default();
+        let mut options = Config::default();
         options.bytes = true;
         options.chars = true;
         options.words = true;
@@ -243,11 +993,247 @@ mod tests {
         assert_eq!(stats.max_line, 73);
     }
 
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_gzipped_detected() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.chars = true;
+        options.words = true;
+        options.lines = true;
+        options.max_line = true;
+        options.min_line = true;
+
+        // no -z flag
+        let stats = Counter::count("tests/poe.unix.gz", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.bytes, 25260);
+        assert_eq!(stats.chars, 25258);
+        assert_eq!(stats.words, 3969);
+        assert_eq!(stats.lines, 887);
+        assert_eq!(stats.max_line, 73);
+
+        // --no-decompress takes precedence over -z
+        options.zipped = true;
+        options.no_decompress = true;
+        let stats = Counter::count("tests/poe.unix", &options);
+        assert!(stats.is_ok());
+        assert_eq!(stats.unwrap().bytes, 25260);
+
+        // count compressed data
+        let mut options = Config::default();
+        options.bytes = true;
+        options.no_decompress = true;
+        let stats = Counter::count("tests/poe.unix.gz", &options);
+        assert!(stats.is_ok());
+        assert_eq!(stats.unwrap().bytes, 8908);
+    }
+
+    // a pipe giving its data one byte at a time
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let n = self.0.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_stream() {
+        let mut options = Config::all();
+        options.gzip_header = true;
+
+        // the compression format is known even if the magic bytes come in several reads
+        let mut files = vec!["tests/poe.unix.gz"];
+        if cfg!(feature = "bzip2") {
+            files.push("tests/poe.unix.bz2");
+        }
+
+        for file_name in files {
+            let data = std::fs::read(file_name).unwrap();
+            let stats = Counter::count_stream(Trickle(&data), &options).unwrap();
+            assert_eq!(stats.lines, 887, "{}", file_name);
+            assert_eq!(stats.words, 3969, "{}", file_name);
+            assert_eq!(stats.compressed_bytes, data.len() as u64);
+        }
+
+        let data = std::fs::read("tests/poe.unix.gz").unwrap();
+        let stats = Counter::count_stream(Trickle(&data), &options).unwrap();
+        assert_eq!(stats.original_name.as_deref(), Some("poe.unix"));
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_gzipped_multi_member() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.chars = true;
+        options.words = true;
+        options.lines = true;
+        options.max_line = true;
+        options.min_line = true;
+
+        // 2 gzip members concatenated
+        let stats = Counter::count("tests/poe.unix.multi.gz", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.bytes, 25260);
+        assert_eq!(stats.chars, 25258);
+        assert_eq!(stats.words, 3969);
+        assert_eq!(stats.lines, 887);
+        assert_eq!(stats.max_line, 73);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_compressed_bytes() {
+        // set options
+        let mut options = Config::default();
+        options.lines = true;
+        options.ratio = true;
+
+        let stats = Counter::count("tests/poe.unix.gz", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.compressed_bytes, 8908);
+        assert_eq!(stats.bytes, 25260);
+        assert_eq!(stats.lines, 887);
+
+        // same value when not compressed
+        let stats = Counter::count("tests/poe.unix", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.compressed_bytes, 25260);
+        assert_eq!(stats.bytes, 25260);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_gzipped_corrupt() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.gzip_header = true;
+
+        // checksum and size are verified
+        let stats = Counter::count("tests/poe.unix.gz", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.bytes, 25260);
+        assert_eq!(stats.original_name, Some("poe.unix".to_string()));
+        assert_eq!(stats.mtime, Some(1613844312));
+
+        // wrong CRC
+        let stats = Counter::count("tests/poe.unix.corrupt.gz", &options);
+        assert!(stats.is_err());
+
+        // missing end of the file
+        let stats = Counter::count("tests/poe.unix.truncated.gz", &options);
+        assert!(stats.is_err());
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn count_salvaged() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.lines = true;
+        options.salvage = true;
+
+        // keep what was decompressed before the end of the file, both with and without
+        // UTF-8 decoding
+        let stats = Counter::count("tests/poe.unix.truncated.gz", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.partial, Some(12464));
+        assert_eq!(stats.bytes, 12464);
+        assert_eq!(stats.lines, 640);
+
+        options.chars = true;
+        let stats = Counter::count("tests/poe.unix.truncated.gz", &options);
+        assert!(stats.is_ok());
+
+        let stats = stats.unwrap();
+
+        assert_eq!(stats.partial, Some(12464));
+        assert_eq!(stats.bytes, 12464);
+        assert_eq!(stats.chars, 12462);
+        assert_eq!(stats.lines, 640);
+
+        // complete files are not partial
+        let stats = Counter::count("tests/poe.unix.gz", &options);
+        assert!(stats.is_ok());
+        assert_eq!(stats.unwrap().partial, None);
+    }
+
+    #[test]
+    #[cfg(all(
+        target_family = "unix",
+        feature = "bzip2",
+        feature = "xz",
+        feature = "zstd",
+        feature = "lz4"
+    ))]
+    fn count_compressed() {
+        // set options
+        let mut options = Config::default();
+        options.bytes = true;
+        options.chars = true;
+        options.words = true;
+        options.lines = true;
+        options.max_line = true;
+        options.min_line = true;
+
+        for file in &[
+            "tests/poe.unix.bz2",
+            "tests/poe.unix.xz",
+            "tests/poe.unix.lzma",
+            "tests/poe.unix.zst",
+            "tests/poe.unix.lz4",
+        ] {
+            let stats = Counter::count(file, &options);
+            assert!(stats.is_ok());
+
+            let stats = stats.unwrap();
+
+            assert_eq!(stats.bytes, 25260);
+            assert_eq!(stats.chars, 25258);
+            assert_eq!(stats.words, 3969);
+            assert_eq!(stats.lines, 887);
+            assert_eq!(stats.max_line, 73);
+        }
+
+        // wrong format
+        options.decompress = Some(Compression::Zstd);
+        let stats = Counter::count("tests/poe.unix.xz", &options);
+        assert!(stats.is_err());
+    }
+
     #[test]
     #[cfg(target_family = "windows")]
     fn count() {
         // set options
-        let mut options = CliOptions::default();
+        let mut options = Config::default();
         options.bytes = true;
         options.chars = true;
         options.words = true;
@@ -272,7 +1258,7 @@ mod tests {
     #[cfg(target_family = "windows")]
     fn count_gzipped() {
         // set options
-        let mut options = CliOptions::default();
+        let mut options = Config::default();
         options.bytes = true;
         options.chars = true;
         options.words = true;
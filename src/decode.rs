@@ -0,0 +1,269 @@
+use std::io::Error;
+
+use chardetng::EncodingDetector;
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+use crate::config::Config;
+
+/// Unicode encodings which can be either sniffed from a BOM or requested with --encoding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Utf32Le,
+    Utf32Be,
+
+    // any other single or multi-byte encoding, as defined by the WHATWG Encoding Standard
+    Legacy(LegacyEncoding),
+}
+
+/// A single or multi-byte encoding other than the Unicode ones, e.g. windows-1252 or Shift_JIS.
+/// It's only built from a label with [`TextEncoding::from_label`] or by guessing it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LegacyEncoding(&'static Encoding);
+
+impl LegacyEncoding {
+    /// canonical name of the encoding
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+}
+
+impl TextEncoding {
+    /// convert an encoding label as given on the command line
+    pub fn from_label(label: &str) -> Option<TextEncoding> {
+        match label.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(TextEncoding::Utf8),
+            "utf-16le" | "utf16le" | "utf-16" | "utf16" => Some(TextEncoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(TextEncoding::Utf16Be),
+            "utf-32le" | "utf32le" | "utf-32" | "utf32" => Some(TextEncoding::Utf32Le),
+            "utf-32be" | "utf32be" => Some(TextEncoding::Utf32Be),
+            // otherwise use the WHATWG labels (e.g.: latin1, windows-1252, shift_jis, gbk, euc-kr)
+            _ => Encoding::for_label_no_replacement(label.trim().as_bytes())
+                .map(TextEncoding::from_whatwg),
+        }
+    }
+
+    /// guess the encoding from the beginning of the input. last is true when the whole input
+    /// is contained in head
+    pub fn detect(head: &[u8], last: bool) -> TextEncoding {
+        let mut detector = EncodingDetector::new();
+        detector.feed(head, last);
+        TextEncoding::from_whatwg(detector.guess(None, true))
+    }
+
+    /// encoding of the input, given its head: a BOM takes precedence, then the explicit encoding
+    /// of BOM-less input, otherwise it's guessed if requested. Also returns the length of the BOM
+    /// to skip. last is true when the whole input is contained in head
+    pub fn select(head: &[u8], last: bool, options: &Config) -> (TextEncoding, usize) {
+        match (TextEncoding::from_bom(head), options.encoding) {
+            (Some((encoding, bom_length)), _) => (encoding, bom_length),
+            (None, Some(encoding)) => (encoding, 0),
+            (None, None) if options.detect_encoding => (TextEncoding::detect(head, last), 0),
+            (None, None) => (TextEncoding::Utf8, 0),
+        }
+    }
+
+    // map an encoding_rs encoding to ours
+    fn from_whatwg(encoding: &'static Encoding) -> TextEncoding {
+        if encoding == UTF_8 {
+            TextEncoding::Utf8
+        } else if encoding == UTF_16LE {
+            TextEncoding::Utf16Le
+        } else if encoding == UTF_16BE {
+            TextEncoding::Utf16Be
+        } else {
+            TextEncoding::Legacy(LegacyEncoding(encoding))
+        }
+    }
+
+    /// canonical name of the encoding
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Utf16Le => "UTF-16LE",
+            TextEncoding::Utf16Be => "UTF-16BE",
+            TextEncoding::Utf32Le => "UTF-32LE",
+            TextEncoding::Utf32Be => "UTF-32BE",
+            TextEncoding::Legacy(encoding) => encoding.name(),
+        }
+    }
+
+    /// look for a BOM at the beginning of the buffer, and return the encoding and the BOM length.
+    /// UTF-32LE needs to be tested before UTF-16LE because they share the same first 2 bytes
+    pub fn from_bom(buf: &[u8]) -> Option<(TextEncoding, usize)> {
+        if buf.starts_with(&[0xFF, 0xFE, 0x00, 0x00]) {
+            Some((TextEncoding::Utf32Le, 4))
+        } else if buf.starts_with(&[0x00, 0x00, 0xFE, 0xFF]) {
+            Some((TextEncoding::Utf32Be, 4))
+        } else if buf.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            Some((TextEncoding::Utf8, 3))
+        } else if buf.starts_with(&[0xFF, 0xFE]) {
+            Some((TextEncoding::Utf16Le, 2))
+        } else if buf.starts_with(&[0xFE, 0xFF]) {
+            Some((TextEncoding::Utf16Be, 2))
+        } else {
+            None
+        }
+    }
+}
+
+// the way input bytes are converted to UTF-8, so counting is always made on UTF-8 data. Invalid
+// sequences are replaced by U+FFFD
+pub enum Transcoder {
+    // UTF-16 and legacy encodings are handled by encoding_rs
+    Whatwg(Decoder),
+
+    // but UTF-32 is not, so decode it by hand. Keep track of incomplete code units
+    // between 2 reads
+    Utf32 { big_endian: bool, carry: Vec<u8> },
+}
+
+impl Transcoder {
+    pub fn new(encoding: TextEncoding) -> Transcoder {
+        match encoding {
+            TextEncoding::Utf8 => unreachable!("UTF-8 input doesn't need to be transcoded"),
+            TextEncoding::Utf16Le => {
+                Transcoder::Whatwg(UTF_16LE.new_decoder_without_bom_handling())
+            }
+            TextEncoding::Utf16Be => {
+                Transcoder::Whatwg(UTF_16BE.new_decoder_without_bom_handling())
+            }
+            TextEncoding::Utf32Le => Transcoder::Utf32 {
+                big_endian: false,
+                carry: Vec::with_capacity(4),
+            },
+            TextEncoding::Utf32Be => Transcoder::Utf32 {
+                big_endian: true,
+                carry: Vec::with_capacity(4),
+            },
+            TextEncoding::Legacy(LegacyEncoding(encoding)) => {
+                Transcoder::Whatwg(encoding.new_decoder_without_bom_handling())
+            }
+        }
+    }
+
+    // transcode the next chunk of input, and append it to the decoded buffer. last is true
+    // when there's no more input, to flush what's left
+    pub fn transcode(
+        &mut self,
+        src: &[u8],
+        last: bool,
+        decoded: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        match self {
+            Transcoder::Whatwg(decoder) => {
+                let max_len = decoder
+                    .max_utf8_buffer_length(src.len())
+                    .ok_or_else(|| Error::other("input too large to be transcoded"))?;
+                let start = decoded.len();
+                decoded.resize(start + max_len, 0);
+
+                let (result, _, written, _) =
+                    decoder.decode_to_utf8(src, &mut decoded[start..], last);
+                debug_assert_eq!(result, CoderResult::InputEmpty);
+                decoded.truncate(start + written);
+            }
+            Transcoder::Utf32 { big_endian, carry } => {
+                // prepend the incomplete code unit left over from the previous read
+                let mut data = std::mem::take(carry);
+                data.extend_from_slice(src);
+
+                let mut units = data.chunks_exact(4);
+                for unit in &mut units {
+                    let unit = [unit[0], unit[1], unit[2], unit[3]];
+                    let value = if *big_endian {
+                        u32::from_be_bytes(unit)
+                    } else {
+                        u32::from_le_bytes(unit)
+                    };
+                    let c = char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER);
+
+                    let mut utf8 = [0u8; 4];
+                    decoded.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                }
+
+                // keep incomplete code unit for the next read
+                carry.extend_from_slice(units.remainder());
+
+                // truncated code unit at the end of the input
+                if last && !carry.is_empty() {
+                    carry.clear();
+                    let mut utf8 = [0u8; 4];
+                    decoded.extend_from_slice(
+                        char::REPLACEMENT_CHARACTER
+                            .encode_utf8(&mut utf8)
+                            .as_bytes(),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_bom() {
+        assert_eq!(
+            TextEncoding::from_bom(&[0xFF, 0xFE, 0x00, 0x00, 0x41]),
+            Some((TextEncoding::Utf32Le, 4))
+        );
+        assert_eq!(
+            TextEncoding::from_bom(&[0xFF, 0xFE, 0x41, 0x00]),
+            Some((TextEncoding::Utf16Le, 2))
+        );
+        assert_eq!(
+            TextEncoding::from_bom(&[0xFE, 0xFF, 0x00, 0x41]),
+            Some((TextEncoding::Utf16Be, 2))
+        );
+        assert_eq!(
+            TextEncoding::from_bom(&[0xEF, 0xBB, 0xBF, 0x41]),
+            Some((TextEncoding::Utf8, 3))
+        );
+        assert_eq!(TextEncoding::from_bom(b"plain text"), None);
+    }
+
+    #[test]
+    fn from_label() {
+        assert_eq!(TextEncoding::from_label("UTF-8"), Some(TextEncoding::Utf8));
+        assert_eq!(
+            TextEncoding::from_label("unicodefffe"),
+            Some(TextEncoding::Utf16Be)
+        );
+        assert_eq!(
+            TextEncoding::from_label("latin1"),
+            Some(TextEncoding::Legacy(LegacyEncoding(
+                encoding_rs::WINDOWS_1252
+            )))
+        );
+        assert_eq!(
+            TextEncoding::from_label("sjis"),
+            Some(TextEncoding::Legacy(LegacyEncoding(encoding_rs::SHIFT_JIS)))
+        );
+        assert_eq!(TextEncoding::from_label("iso-2022-kr"), None);
+        assert_eq!(TextEncoding::from_label("foo"), None);
+    }
+
+    #[test]
+    fn decode_utf32() {
+        let input: Vec<u8> = "αβγ\n"
+            .chars()
+            .flat_map(|c| (c as u32).to_be_bytes().to_vec())
+            .collect();
+
+        // code units split between buffers
+        let mut transcoder = Transcoder::new(TextEncoding::Utf32Be);
+        let mut decoded = Vec::new();
+        for piece in input.chunks(3) {
+            transcoder.transcode(piece, false, &mut decoded).unwrap();
+        }
+        transcoder.transcode(&[], true, &mut decoded).unwrap();
+        assert_eq!(decoded, "αβγ\n".as_bytes());
+    }
+}
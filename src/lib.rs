@@ -0,0 +1,50 @@
+//! Count bytes, chars, words and lines of files, like `wc`, but also of compressed files,
+//! archive members and files in other encodings than UTF-8.
+//!
+//! The [`Config`] tells what to count and how to read the input, and [`Counter`] returns the
+//! [`Stats`] of a file or of any reader. Data given piece by piece is counted with a
+//! [`StreamingCounter`], and data passing through with a [`CountingReader`] or a
+//! [`CountingWriter`]:
+//!
+//! ```
+//! use awc::{Config, Counter};
+//!
+//! let config = Config::all();
+//! let stats = Counter::count_reader(&b"hello world\nand goodbye"[..], &config)?;
+//!
+//! assert_eq!(stats.words, 4);
+//! assert_eq!(stats.lines, 2);
+//! assert_eq!(stats.max_line, 11);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Other counts than the built-in ones are added to the configuration as [`Metric`]s.
+//!
+//! Compression and archive formats other than gzip are cargo features, all enabled by default.
+//! The `cli` feature is only needed by the `awc` executable, and the `async` feature adds
+//! `Counter::count_async()` to count tokio readers.
+
+mod adapter;
+mod archive;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod chunk;
+mod compression;
+mod config;
+mod counter;
+mod decode;
+mod metric;
+mod pipeline;
+mod scan;
+mod stats;
+mod streaming;
+
+pub use adapter::{CountingReader, CountingWriter};
+pub use archive::{Archive, ArchiveStats, EntryStats};
+pub use compression::Compression;
+pub use config::{Config, InvalidMode};
+pub use counter::Counter;
+pub use decode::{LegacyEncoding, TextEncoding};
+pub use metric::{Builtin, Metric, MetricValue};
+pub use stats::Stats;
+pub use streaming::StreamingCounter;
@@ -1,12 +1,12 @@
+use std::collections::HashMap;
 use std::env;
-use std::io::{self, BufRead};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
 
-mod stats;
-use stats::Stats;
-
-mod counter;
-use counter::Counter;
+use awc::{Archive, ArchiveStats, Counter, CountingReader, Stats};
 
 #[cfg(target_family = "windows")]
 use glob::glob;
@@ -17,14 +17,21 @@ use options::CliOptions;
 fn main() -> Result<(), std::io::Error> {
     // get args from the command line
     let args: Vec<String> = env::args().collect();
-    println!("args={:?}", args);
 
     // used to add stats to print out total
     let mut sum_stats = Stats::default();
 
     // convert arguments into flags
     let options = CliOptions::check_args(&args);
-    println!("options={:?}", options);
+
+    // in the middle of a pipeline, only the standard input is read
+    if options.tee {
+        if !file_args(&args).is_empty() {
+            eprintln!("--tee reads the standard input, and can't be given files");
+            std::process::exit(1);
+        }
+        return tee(&options);
+    }
 
     // get files from arguments
     let mut files = get_files(&args);
@@ -39,45 +46,215 @@ fn main() -> Result<(), std::io::Error> {
             .collect();
     }
 
-    // now just coutn for each file found
-    for f in &files {
-        match Counter::count(f, &options) {
-            Ok(stats) => {
-                stats.print_results(&options, f);
-                sum_stats += stats;
+    // with --verify, any error leads to a non-zero exit code
+    let mut failed = false;
+
+    // files are counted by a pool of threads, each one picking the next file to count. Results
+    // are printed in the order of the arguments, as soon as they are available
+    let jobs = options.config.jobs().min(files.len()).max(1);
+    let next = AtomicUsize::new(0);
+
+    // threads not used to count files in parallel are used to split large files
+    let mut file_options = options.clone();
+    file_options.config.jobs = (options.config.jobs() / jobs).max(1);
+
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let (files, options, next) = (&files, &file_options, &next);
+
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= files.len() {
+                    break;
+                }
+
+                let outcome = Outcome::count(&files[i], options);
+                if tx.send((i, outcome)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        // keep results received out of order until their turn comes
+        let mut reorder = Reorder::new();
+
+        for (i, outcome) in rx {
+            for (i, outcome) in reorder.push(i, outcome) {
+                failed |= outcome.print_results(&options, &files[i], &mut sum_stats);
             }
-            Err(e) => eprintln!("error '{}' when counting into file {}", e, f.display()),
-        };
-    }
+        }
+    });
 
     // print out total if any
     if files.len() > 1 {
-        sum_stats.print_results(&options, "total");
+        sum_stats.print_results(&options.config, "total");
+    }
+
+    if options.verify && failed {
+        std::process::exit(1);
     }
 
     Ok(())
 }
 
+// Results received in any order, handed over in the order of their indexes
+struct Reorder<T> {
+    pending: HashMap<usize, T>,
+    next: usize,
+}
+
+impl<T> Reorder<T> {
+    fn new() -> Reorder<T> {
+        Reorder {
+            pending: HashMap::new(),
+            next: 0,
+        }
+    }
+
+    // add the result of index i, and return the ones whose turn has come, with their indexes
+    fn push(&mut self, i: usize, result: T) -> Vec<(usize, T)> {
+        self.pending.insert(i, result);
+
+        let mut ready = Vec::new();
+        while let Some(result) = self.pending.remove(&self.next) {
+            ready.push((self.next, result));
+            self.next += 1;
+        }
+
+        ready
+    }
+}
+
+// what was counted for a single file of the command line
+enum Outcome {
+    File(Result<Stats, io::Error>),
+    Archive(Result<ArchiveStats, io::Error>),
+}
+
+impl Outcome {
+    fn count(f: &Path, options: &CliOptions) -> Outcome {
+        // each archive member is counted separately
+        if options.archive {
+            Outcome::Archive(Archive::count(f, &options.config))
+        } else {
+            Outcome::File(Counter::count(f, &options.config))
+        }
+    }
+
+    // print counts and errors, and add counts to the total. Returns true if anything went wrong
+    fn print_results(self, options: &CliOptions, f: &Path, sum_stats: &mut Stats) -> bool {
+        let mut failed = false;
+
+        match self {
+            Outcome::File(result) => {
+                failed |= print_file_results(result, options, &f.display().to_string(), sum_stats)
+            }
+
+            // the archive subtotal comes after its members
+            Outcome::Archive(Ok(archive)) => {
+                let mut archive_stats = Stats::default();
+                for (name, result) in archive.entries {
+                    failed |= print_file_results(result, options, &name, &mut archive_stats);
+                }
+
+                // the archive is compressed as a whole, not member by member
+                archive_stats.compressed_bytes = archive.compressed_bytes;
+                archive_stats.print_results(&options.config, f);
+                *sum_stats += archive_stats;
+            }
+            Outcome::Archive(Err(e)) => {
+                eprintln!("error '{}' when reading archive {}", e, f.display());
+                failed = true;
+            }
+        }
+
+        failed
+    }
+}
+
+// print counts or error of a single file or archive member. Returns true if anything went wrong
+fn print_file_results(
+    result: Result<Stats, io::Error>,
+    options: &CliOptions,
+    name: &str,
+    sum_stats: &mut Stats,
+) -> bool {
+    match result {
+        Ok(stats) => {
+            stats.print_results(&options.config, name);
+
+            let partial = stats.partial;
+            *sum_stats += stats;
+
+            if let Some(offset) = partial {
+                eprintln!(
+                    "file {} is truncated or corrupt, counts stop at decompressed offset {}",
+                    name, offset
+                );
+                return true;
+            }
+            false
+        }
+        Err(e) => {
+            eprintln!("error '{}' when counting into file {}", e, name);
+            true
+        }
+    }
+}
+
+// copy the standard input to the standard output while counting it, and print the counts to the
+// standard error at EOF
+fn tee(options: &CliOptions) -> Result<(), io::Error> {
+    let mut reader = CountingReader::new(io::stdin().lock(), &options.config);
+    let mut writer = BufWriter::new(io::stdout().lock());
+
+    io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+
+    match reader.finish() {
+        Ok(stats) => stats.write_results(&mut io::stderr(), &options.config, "-"),
+        Err(e) => {
+            eprintln!("error '{}' when counting the standard input", e);
+            if options.verify {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
+
 // returns the list of files from command line
 #[cfg(target_family = "unix")]
-fn get_files<'a>(args: &'a [String]) -> Vec<PathBuf> {
-    args.iter()
-        .skip(1) // first element is the executable file name, so skip it
-        .filter(|&x| !x.starts_with("-")) // and only keep non-flags (not starting with "-")
-        .map(|x| PathBuf::from(x))
-        .collect()
+fn get_files(args: &[String]) -> Vec<PathBuf> {
+    file_args(args)
+}
+
+// arguments which are not flags, nor values of flags. "-" is the standard input
+fn file_args(args: &[String]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    // first element is the executable file name, so skip it
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if CliOptions::takes_value(arg) {
+            args.next();
+        } else if arg == "-" || !arg.starts_with('-') {
+            files.push(PathBuf::from(arg));
+        }
+    }
+
+    files
 }
 
 #[cfg(target_family = "windows")]
-fn get_files<'a>(args: &'a [String]) -> Vec<PathBuf> {
+fn get_files(args: &[String]) -> Vec<PathBuf> {
     // fetch glob because on Windows, no file name expansion is made. So if we pass '*.jpg', we only
     // get this
-    let files: Vec<PathBuf> = args
-        .iter()
-        .skip(1) // first element is the executable file name, so skip it
-        .filter(|&x| !x.starts_with("-")) // and only keep non-flags (not starting with "-")
-        .map(|x| PathBuf::from(x))
-        .collect();
+    let files = file_args(args);
 
     // depending on how many arguments we got, we can process
     let pattern: &str;
@@ -86,7 +263,8 @@ fn get_files<'a>(args: &'a [String]) -> Vec<PathBuf> {
         // no files means sdtin
         0 => return Vec::new(),
 
-        // 1 files means a pattern
+        // 1 files means a pattern, apart from stdin
+        1 if files[0] == PathBuf::from("-") => return files,
         1 => pattern = files[0].to_str().unwrap(),
 
         // 2 or more means a list of files without globs
@@ -118,3 +296,68 @@ fn get_files<'a>(args: &'a [String]) -> Vec<PathBuf> {
 
     v
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn file_args() {
+        let args = strings(&["awc", "-l", "-j", "2", "a", "--jobs", "3", "-", "-j4", "b"]);
+        assert_eq!(
+            super::file_args(&args),
+            vec![PathBuf::from("a"), PathBuf::from("-"), PathBuf::from("b")]
+        );
+
+        let args = strings(&["awc", "--encoding", "latin1", "a", "--encoding=sjis", "b"]);
+        assert_eq!(
+            super::file_args(&args),
+            vec![PathBuf::from("a"), PathBuf::from("b")]
+        );
+    }
+
+    #[test]
+    fn reorder() {
+        let mut reorder = Reorder::new();
+
+        // results completed out of order wait for the previous ones
+        assert!(reorder.push(2, "c").is_empty());
+        assert!(reorder.push(1, "b").is_empty());
+        assert_eq!(reorder.push(0, "a"), vec![(0, "a"), (1, "b"), (2, "c")]);
+        assert_eq!(reorder.push(3, "d"), vec![(3, "d")]);
+        assert!(reorder.push(5, "f").is_empty());
+        assert_eq!(reorder.push(4, "e"), vec![(4, "e"), (5, "f")]);
+    }
+
+    #[test]
+    fn errors_attributed_to_their_file() {
+        let files = strings(&["tests/poe.unix", "tests/missing", "tests/utf8.txt"]);
+        let mut options = CliOptions::check_args(&strings(&["-l"]));
+        options.config.jobs = 1;
+
+        let mut reorder = Reorder::new();
+        let mut results = Vec::new();
+
+        // the failing file completes last
+        for &i in &[2, 0, 1] {
+            let outcome = Outcome::count(Path::new(&files[i]), &options);
+            results.extend(reorder.push(i, outcome));
+        }
+
+        let mut sum_stats = Stats::default();
+        let failed: Vec<(usize, bool)> = results
+            .into_iter()
+            .map(|(i, outcome)| {
+                let failed = outcome.print_results(&options, Path::new(&files[i]), &mut sum_stats);
+                (i, failed)
+            })
+            .collect();
+
+        assert_eq!(failed, vec![(0, false), (1, true), (2, false)]);
+        assert_eq!(sum_stats.lines, 887 + 1);
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::stats::Stats;
+
+/// A metric and its value for a given input
+pub type MetricValue = (Arc<dyn Metric>, u64);
+
+/// A count made on the data of each input, and printed in its own column. The built-in counts
+/// are metrics too (see [`Builtin`]). Metrics added to the
+/// [`Config::metrics`](crate::Config::metrics) are counted along with them, and printed after
+/// them.
+///
+/// ```
+/// use std::sync::Arc;
+/// use awc::{Config, Counter, Metric, Stats};
+///
+/// // number of ASCII digits
+/// #[derive(Default)]
+/// struct Digits(u64);
+///
+/// impl Metric for Digits {
+///     fn name(&self) -> &str {
+///         "digits"
+///     }
+///
+///     fn fresh(&self) -> Box<dyn Metric> {
+///         Box::new(Digits::default())
+///     }
+///
+///     fn observe(&mut self, buf: &[u8]) {
+///         self.0 += buf.iter().filter(|b| b.is_ascii_digit()).count() as u64;
+///     }
+///
+///     fn finalize(&mut self, _counts: &Stats) -> u64 {
+///         self.0
+///     }
+/// }
+///
+/// let mut config = Config::all();
+/// config.metrics.push(Arc::new(Digits::default()));
+///
+/// let stats = Counter::count_reader(&b"1 2 3 go"[..], &config)?;
+/// assert_eq!(stats.metrics[0].1, 3);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub trait Metric: Send + Sync {
+    /// name of the count, e.g. "words"
+    fn name(&self) -> &str;
+
+    /// a metric of the same kind with nothing observed yet, to count a new input
+    fn fresh(&self) -> Box<dyn Metric>;
+
+    /// count the next piece of the UTF-8 data. Pieces are given in order, but can be split
+    /// anywhere, even in the middle of a line or of a UTF-8 sequence
+    fn observe(&mut self, buf: &[u8]);
+
+    /// value of the count once all data is observed. `counts` are the built-in counts of the
+    /// same data, made in a single pass, which a metric can derive from instead of observing
+    /// the data itself. Only the counts asked for by the configuration are set
+    fn finalize(&mut self, counts: &Stats) -> u64;
+
+    /// combine the values of 2 inputs, e.g. to get the total of several files
+    fn merge(&self, total: u64, value: u64) -> u64 {
+        total + value
+    }
+
+    /// format a value for output
+    fn render(&self, value: u64) -> String {
+        format!("{:8}", value)
+    }
+}
+
+impl fmt::Debug for dyn Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Metric({})", self.name())
+    }
+}
+
+/// The counts built in awc, in the order they are printed. They're requested by the boolean
+/// fields of the [`Config`], and counted together in a single pass over the data: as
+/// [`Metric`]s, they observe nothing, and take their values from the counts of that pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    Bytes,
+    Chars,
+    Words,
+    Lines,
+    MinLine,
+    MaxLine,
+}
+
+impl Builtin {
+    /// all built-in counts, in output order
+    pub const ALL: [Builtin; 6] = [
+        Builtin::Bytes,
+        Builtin::Chars,
+        Builtin::Words,
+        Builtin::Lines,
+        Builtin::MinLine,
+        Builtin::MaxLine,
+    ];
+
+    /// name of the count
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Bytes => "bytes",
+            Builtin::Chars => "chars",
+            Builtin::Words => "words",
+            Builtin::Lines => "lines",
+            Builtin::MinLine => "min_line",
+            Builtin::MaxLine => "max_line",
+        }
+    }
+
+    /// true if the configuration asks for this count
+    pub fn requested(self, config: &Config) -> bool {
+        match self {
+            Builtin::Bytes => config.bytes,
+            Builtin::Chars => config.chars,
+            Builtin::Words => config.words,
+            Builtin::Lines => config.lines,
+            Builtin::MinLine => config.min_line,
+            Builtin::MaxLine => config.max_line,
+        }
+    }
+
+    /// value of this count in the statistics
+    pub fn value(self, stats: &Stats) -> u64 {
+        match self {
+            Builtin::Bytes => stats.bytes,
+            Builtin::Chars => stats.chars,
+            Builtin::Words => stats.words,
+            Builtin::Lines => stats.lines,
+            Builtin::MinLine => stats.min_line,
+            Builtin::MaxLine => stats.max_line,
+        }
+    }
+
+    // where the value of this count is kept in the statistics
+    pub(crate) fn value_mut(self, stats: &mut Stats) -> &mut u64 {
+        match self {
+            Builtin::Bytes => &mut stats.bytes,
+            Builtin::Chars => &mut stats.chars,
+            Builtin::Words => &mut stats.words,
+            Builtin::Lines => &mut stats.lines,
+            Builtin::MinLine => &mut stats.min_line,
+            Builtin::MaxLine => &mut stats.max_line,
+        }
+    }
+}
+
+impl Metric for Builtin {
+    fn name(&self) -> &str {
+        Builtin::name(*self)
+    }
+
+    fn fresh(&self) -> Box<dyn Metric> {
+        Box::new(*self)
+    }
+
+    // counted in the pass shared by all built-in counts
+    fn observe(&mut self, _buf: &[u8]) {}
+
+    fn finalize(&mut self, counts: &Stats) -> u64 {
+        self.value(counts)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+    use super::*;
+    use crate::counter::Counter;
+
+    // number of lines longer than 60 chars, which needs to keep track of lines split between
+    // pieces
+    #[derive(Default)]
+    struct LongLines {
+        length: u64,
+        count: u64,
+    }
+
+    impl Metric for LongLines {
+        fn name(&self) -> &str {
+            "long_lines"
+        }
+
+        fn fresh(&self) -> Box<dyn Metric> {
+            Box::new(LongLines::default())
+        }
+
+        fn observe(&mut self, buf: &[u8]) {
+            for &b in buf {
+                if b == b'\n' {
+                    self.count += u64::from(self.length > 60);
+                    self.length = 0;
+                } else if b & 0xC0 != 0x80 {
+                    self.length += 1;
+                }
+            }
+        }
+
+        fn finalize(&mut self, _counts: &Stats) -> u64 {
+            self.count + u64::from(self.length > 60)
+        }
+
+        fn merge(&self, total: u64, value: u64) -> u64 {
+            total.max(value)
+        }
+    }
+
+    // mean number of chars per line, derived from the built-in counts
+    struct MeanLine;
+
+    impl Metric for MeanLine {
+        fn name(&self) -> &str {
+            "mean_line"
+        }
+
+        fn fresh(&self) -> Box<dyn Metric> {
+            Box::new(MeanLine)
+        }
+
+        fn observe(&mut self, _buf: &[u8]) {}
+
+        fn finalize(&mut self, counts: &Stats) -> u64 {
+            counts.chars.checked_div(counts.lines).unwrap_or(0)
+        }
+    }
+
+    fn long_lines(file_name: &str) -> u64 {
+        std::fs::read_to_string(file_name)
+            .unwrap()
+            .lines()
+            .filter(|line| line.chars().count() > 60)
+            .count() as u64
+    }
+
+    #[test]
+    fn builtin() {
+        let config = Config::all();
+        let stats = Counter::count("tests/poe.unix", &config).unwrap();
+
+        let values: Vec<u64> = Builtin::ALL.iter().map(|b| b.value(&stats)).collect();
+        assert_eq!(
+            values,
+            vec![
+                stats.bytes,
+                stats.chars,
+                stats.words,
+                stats.lines,
+                stats.min_line,
+                stats.max_line
+            ]
+        );
+        assert!(Builtin::ALL.iter().all(|b| b.requested(&config)));
+        assert!(!Builtin::Words.requested(&Config::default()));
+
+        // as metrics, they take their values from the shared pass
+        for b in &Builtin::ALL {
+            let mut metric = b.fresh();
+            metric.observe(b"ignored");
+            assert_eq!(metric.name(), b.name());
+            assert_eq!(metric.finalize(&stats), b.value(&stats));
+        }
+        assert_eq!(Builtin::Lines.merge(3, 4), 7);
+        assert_eq!(Builtin::Lines.render(887), "     887");
+    }
+
+    #[test]
+    fn extra() {
+        let mut config = Config::default();
+        config.bytes = true;
+        config.metrics.push(Arc::new(LongLines::default()));
+        assert!(config.any_count());
+
+        // neither the metadata nor the parallel ranges are enough
+        config.jobs = 4;
+        let mut total = Stats::default();
+        for file_name in &["tests/poe.unix", "tests/odysseus.unix", "tests/poe.unix.gz"] {
+            let stats = Counter::count(file_name, &config).unwrap();
+            let expected = long_lines(file_name.trim_end_matches(".gz"));
+            assert_eq!(stats.metrics.len(), 1);
+            assert_eq!(stats.metrics[0].0.name(), "long_lines");
+            assert_eq!(stats.metrics[0].1, expected);
+            assert!(stats.bytes > 0);
+
+            total += stats;
+        }
+
+        // totals are merged by the metric
+        assert_eq!(total.metrics[0].1, long_lines("tests/poe.unix"));
+
+        // values in output order
+        let values = total.values(&config);
+        let names: Vec<&str> = values.iter().map(|(metric, _)| metric.name()).collect();
+        assert_eq!(names, vec!["bytes", "long_lines"]);
+        assert_eq!(values[1].1, long_lines("tests/poe.unix"));
+    }
+
+    #[test]
+    fn derived() {
+        let mut config = Config::default();
+        config.chars = true;
+        config.lines = true;
+        config.metrics.push(Arc::new(MeanLine));
+
+        let stats = Counter::count("tests/poe.unix", &config).unwrap();
+        assert_eq!(stats.metrics[0].1, stats.chars / stats.lines);
+    }
+}
@@ -1,33 +1,26 @@
 use std::char;
 
+use awc::{Compression, Config, InvalidMode, TextEncoding};
+
 // Options as interpreted or inferred from the command line
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct CliOptions {
-    // request to output the number of bytes
-    pub bytes: bool,
-
-    // request to output the number of chars
-    pub chars: bool,
-
-    // request to output the number of words (blank-separated)
-    pub words: bool,
-
-    // request to output the number of lines
-    pub lines: bool,
+    // what to count, and how
+    pub config: Config,
 
-    // request to output the maximum length line
-    pub max_line: bool,
+    // files are archives, and each member is counted separately
+    pub archive: bool,
 
-    // request to output the minimum length line
-    pub min_line: bool,
+    // exit with an error code if a file can't be entirely read, e.g.: corrupt compressed data
+    pub verify: bool,
 
-    // whether file is zipped
-    pub zipped: bool,
+    // copy the standard input to the standard output, and print its counts to the standard error
+    pub tee: bool,
 }
 
 impl CliOptions {
     // manage arguments from the command line
-    pub fn check_args<'a>(args: &'a [String]) -> CliOptions {
+    pub fn check_args(args: &[String]) -> CliOptions {
         let mut options = CliOptions::default();
 
         // no option passed ? assume we want all of them
@@ -36,19 +29,62 @@ impl CliOptions {
             return options;
         }
 
-        // check flags only (so use filter combinator)
-        for arg in args.iter().filter(|x| x.starts_with("-")) {
+        // check flags only, the values of flags being taken along with them
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') {
+                continue;
+            }
+
             match arg.as_str() {
                 // manage first single flags
                 "-h" | "--help" => CliOptions::print_help(),
-                "-b" | "--bytes" => options.bytes = true,
-                "-c" | "--chars" => options.chars = true,
-                "-w" | "--words" => options.words = true,
-                "-l" | "--lines" => options.lines = true,
-                "-z" | "--zip" => options.zipped = true,
-                "-L" | "--max-line-length" => options.max_line = true,
-                "-M" | "--min-line-length" => options.min_line = true,
+                "-b" | "--bytes" => options.config.bytes = true,
+                "-c" | "--chars" => options.config.chars = true,
+                "-w" | "--words" => options.config.words = true,
+                "-l" | "--lines" => options.config.lines = true,
+                "-z" | "--zip" => options.config.zipped = true,
+                "-L" | "--max-line-length" => options.config.max_line = true,
+                "-M" | "--min-line-length" => options.config.min_line = true,
                 "-a" | "--all" => options.set_all(),
+                "--no-decompress" => options.config.no_decompress = true,
+                "--archive" => options.archive = true,
+                "--ratio" => options.config.ratio = true,
+                "--verify" => options.verify = true,
+                "--gzip-header" => options.config.gzip_header = true,
+                "--salvage" => options.config.salvage = true,
+                "--mmap" => options.config.mmap = true,
+                "--tee" => options.tee = true,
+                // values are either attached or the next argument
+                s if CliOptions::takes_value(s) => {
+                    let value = args.next().map(String::as_str).unwrap_or("");
+                    options.set_value(s, value);
+                }
+                s if s.starts_with("--jobs=") || s.starts_with("-j") => {
+                    let value = s.trim_start_matches("--jobs=").trim_start_matches("-j");
+                    options.config.jobs = CliOptions::jobs(value);
+                }
+                // flags with a value
+                "--invalid=strict" => options.config.invalid = InvalidMode::Strict,
+                "--invalid=lossy" => options.config.invalid = InvalidMode::Lossy,
+                "--invalid=report" => options.config.invalid = InvalidMode::Report,
+                s if s.starts_with("--decompress=") => {
+                    let name = &s["--decompress=".len()..];
+                    match Compression::from_name(name) {
+                        Some(compression) => options.config.decompress = Some(compression),
+                        None => {
+                            eprintln!("unknown compression format '{}'", name);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                s if s.starts_with("--encoding=") => {
+                    options.set_encoding(&s["--encoding=".len()..]);
+                }
+                s if s.starts_with("--") => {
+                    eprintln!("unknown flag '{}'", s);
+                    std::process::exit(1);
+                }
                 // now check for combined flags. E.g: -bcw
                 &_ => {
                     let maybe_flags: Vec<char> = arg.as_str().chars().collect();
@@ -59,31 +95,76 @@ impl CliOptions {
             }
         }
 
+        // only non-counting flags passed ? assume we want all counts
+        if !options.config.any_count() {
+            options.set_all();
+        }
+
         options
     }
 
+    // true if the flag is followed by its value as a separate argument, which is not a file
+    pub fn takes_value(arg: &str) -> bool {
+        arg == "-j" || arg == "--jobs" || arg == "--encoding"
+    }
+
+    // set the value of a flag given as a separate argument
+    fn set_value(&mut self, flag: &str, value: &str) {
+        match flag {
+            "--encoding" => self.set_encoding(value),
+            _ => self.config.jobs = CliOptions::jobs(value),
+        }
+    }
+
+    // encoding given by its label, or auto to guess it
+    fn set_encoding(&mut self, label: &str) {
+        if label == "auto" {
+            self.config.detect_encoding = true;
+            return;
+        }
+
+        match TextEncoding::from_label(label) {
+            Some(encoding) => self.config.encoding = Some(encoding),
+            None => {
+                eprintln!("unknown encoding '{}'", label);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // number of jobs given on the command line
+    fn jobs(value: &str) -> usize {
+        match value.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => jobs,
+            _ => {
+                eprintln!("invalid number of jobs '{}'", value);
+                std::process::exit(1);
+            }
+        }
+    }
+
     // set individual flag
     fn maybe_flags(char_flag: char, options: &mut CliOptions) {
         match char_flag {
-            'b' => options.bytes = true,
-            'c' => options.chars = true,
-            'w' => options.words = true,
-            'l' => options.lines = true,
-            'z' => options.zipped = true,
-            'L' => options.max_line = true,
-            'M' => options.min_line = true,
+            'b' => options.config.bytes = true,
+            'c' => options.config.chars = true,
+            'w' => options.config.words = true,
+            'l' => options.config.lines = true,
+            'z' => options.config.zipped = true,
+            'L' => options.config.max_line = true,
+            'M' => options.config.min_line = true,
             _ => (),
         }
     }
 
     // set all flags to true
     fn set_all(&mut self) {
-        self.bytes = true;
-        self.chars = true;
-        self.words = true;
-        self.lines = true;
-        self.max_line = true;
-        self.min_line = true;
+        self.config.bytes = true;
+        self.config.chars = true;
+        self.config.words = true;
+        self.config.lines = true;
+        self.config.max_line = true;
+        self.config.min_line = true;
     }
 
     // just print out help text
@@ -102,44 +183,107 @@ mod tests {
     fn check_args() {
         let args = vec!["-b".to_string()];
         let options = CliOptions::check_args(&args);
-        assert!(options.bytes);
+        assert!(options.config.bytes);
 
         let args = vec!["-c".to_string()];
         let options = CliOptions::check_args(&args);
-        assert!(options.chars);
+        assert!(options.config.chars);
 
         let args = vec!["-w".to_string()];
         let options = CliOptions::check_args(&args);
-        assert!(options.words);
+        assert!(options.config.words);
 
         let args = vec!["-l".to_string()];
         let options = CliOptions::check_args(&args);
-        assert!(options.lines);
+        assert!(options.config.lines);
 
         let args = vec!["-L".to_string()];
         let options = CliOptions::check_args(&args);
-        assert!(options.max_line);
+        assert!(options.config.max_line);
 
         let args = vec!["-M".to_string()];
         let options = CliOptions::check_args(&args);
-        assert!(options.min_line);
+        assert!(options.config.min_line);
 
         let args = vec!["-bcwlLM".to_string()];
         let options = CliOptions::check_args(&args);
-        assert!(options.bytes);
-        assert!(options.chars);
-        assert!(options.words);
-        assert!(options.lines);
-        assert!(options.max_line);
-        assert!(options.min_line);
+        assert!(options.config.bytes);
+        assert!(options.config.chars);
+        assert!(options.config.words);
+        assert!(options.config.lines);
+        assert!(options.config.max_line);
+        assert!(options.config.min_line);
+
+        let args = vec!["--encoding=UTF-16BE".to_string()];
+        let options = CliOptions::check_args(&args);
+        assert_eq!(options.config.encoding, Some(TextEncoding::Utf16Be));
+        assert!(options.config.bytes);
+        assert!(options.config.min_line);
+
+        let args: Vec<String> = vec!["-w", "--encoding", "shift_jis", "file"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let options = CliOptions::check_args(&args);
+        assert_eq!(
+            options.config.encoding,
+            TextEncoding::from_label("shift_jis")
+        );
+        assert!(options.config.words);
+        assert!(!options.config.lines);
+
+        for args in &[vec!["--encoding=auto"], vec!["--encoding", "auto"]] {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            let options = CliOptions::check_args(&args);
+            assert!(options.config.detect_encoding);
+            assert!(options.config.encoding.is_none());
+        }
+
+        let args = vec!["--invalid=report".to_string()];
+        let options = CliOptions::check_args(&args);
+        assert_eq!(options.config.invalid, InvalidMode::Report);
+
+        let args = vec!["--decompress=zst".to_string()];
+        let options = CliOptions::check_args(&args);
+        assert_eq!(options.config.decompress, Some(Compression::Zstd));
+
+        let args = vec!["-j4".to_string()];
+        let options = CliOptions::check_args(&args);
+        assert_eq!(options.config.jobs(), 4);
+
+        let args = vec!["--jobs=2".to_string()];
+        let options = CliOptions::check_args(&args);
+        assert_eq!(options.config.jobs(), 2);
+
+        let args: Vec<String> = vec!["-l", "-j", "3", "file"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let options = CliOptions::check_args(&args);
+        assert_eq!(options.config.jobs(), 3);
+        assert!(options.config.lines);
+        assert!(!options.config.words);
+
+        let args = vec!["--jobs".to_string(), "5".to_string()];
+        let options = CliOptions::check_args(&args);
+        assert_eq!(options.config.jobs(), 5);
+
+        let args = vec!["-b".to_string()];
+        let options = CliOptions::check_args(&args);
+        assert!(options.config.jobs() >= 1);
+
+        let args = vec!["--tee".to_string()];
+        let options = CliOptions::check_args(&args);
+        assert!(options.tee);
+        assert!(options.config.words);
 
         let args = vec!["/var/log/syslog".to_string()];
         let options = CliOptions::check_args(&args);
-        assert!(options.bytes);
-        assert!(options.chars);
-        assert!(options.words);
-        assert!(options.lines);
-        assert!(options.max_line);
-        assert!(options.min_line);
+        assert!(options.config.bytes);
+        assert!(options.config.chars);
+        assert!(options.config.words);
+        assert!(options.config.lines);
+        assert!(options.config.max_line);
+        assert!(options.config.min_line);
     }
 }
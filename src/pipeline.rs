@@ -0,0 +1,142 @@
+use std::io::{Error, ErrorKind, Read};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread::Scope;
+
+// size of the buffers sent from the reading thread
+const BUFFER_SIZE: usize = 256 * 1024;
+
+// number of buffers the reading thread can get ahead of the consumer
+const DEPTH: usize = 4;
+
+// A reader whose data is read by another thread, e.g. to decompress data while it's counted.
+// The other thread stops when this reader is dropped.
+pub struct PipeReader {
+    rx: Receiver<Result<Vec<u8>, Error>>,
+
+    // buffer being consumed
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl PipeReader {
+    // read from the inner reader in a thread of the scope
+    pub fn spawn<'scope, R: Read + Send + 'scope>(
+        scope: &'scope Scope<'scope, '_>,
+        mut inner: R,
+    ) -> PipeReader {
+        let (tx, rx) = sync_channel(DEPTH);
+
+        scope.spawn(move || loop {
+            let mut buffer = vec![0u8; BUFFER_SIZE];
+            let (length, error) = fill(&mut inner, &mut buffer);
+            buffer.truncate(length);
+
+            // data read before an error is sent first
+            if length > 0 && tx.send(Ok(buffer)).is_err() {
+                break;
+            }
+
+            match error {
+                Some(e) => {
+                    let _ = tx.send(Err(e));
+                    break;
+                }
+                None if length == 0 => break,
+                None => (),
+            }
+        });
+
+        PipeReader {
+            rx,
+            buffer: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+// read until the buffer is full, EOF or an error
+fn fill<R: Read>(reader: &mut R, buffer: &mut [u8]) -> (usize, Option<Error>) {
+    let mut length = 0;
+
+    while length < buffer.len() {
+        match reader.read(&mut buffer[length..]) {
+            Ok(0) => break,
+            Ok(n) => length += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return (length, Some(e)),
+        }
+    }
+
+    (length, None)
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.pos == self.buffer.len() {
+            match self.rx.recv() {
+                Ok(Ok(buffer)) => {
+                    self.buffer = buffer;
+                    self.pos = 0;
+                }
+                Ok(Err(e)) => return Err(e),
+                // the reading thread is done
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let available = &self.buffer[self.pos..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fails after some data
+    struct Failing(usize);
+
+    impl Read for Failing {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            if self.0 == 0 {
+                return Err(Error::other("broken"));
+            }
+            let n = self.0.min(buf.len()).min(1000);
+            buf[..n].fill(b'x');
+            self.0 -= n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn pipe_reader() {
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+
+        let copy = std::thread::scope(|scope| {
+            let mut reader = PipeReader::spawn(scope, &data[..]);
+            let mut copy = Vec::new();
+            reader.read_to_end(&mut copy).unwrap();
+            copy
+        });
+        assert_eq!(copy, data);
+
+        // data before the error is kept
+        std::thread::scope(|scope| {
+            let mut reader = PipeReader::spawn(scope, Failing(300_000));
+            let mut copy = Vec::new();
+            assert!(reader.read_to_end(&mut copy).is_err());
+            assert_eq!(copy.len(), 300_000);
+        });
+
+        // the reading thread stops when the reader is dropped
+        std::thread::scope(|scope| {
+            let mut reader = PipeReader::spawn(scope, std::io::repeat(0));
+            let mut buf = [0u8; 10];
+            reader.read_exact(&mut buf).unwrap();
+        });
+    }
+}
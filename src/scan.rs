@@ -0,0 +1,427 @@
+// Counting primitives working on raw bytes. The implementation is chosen at runtime from the
+// CPU features: AVX2 or SSE2 on x86_64, and a scalar fallback everywhere else. All of them
+// give the same results.
+
+// number of newlines
+pub fn count_newlines(buf: &[u8]) -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { x86::count_newlines_avx2(buf) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { x86::count_newlines_sse2(buf) };
+        }
+    }
+
+    scalar::count_newlines(buf)
+}
+
+// call found() with the position of each newline, in order
+pub fn for_each_newline<F: FnMut(usize)>(buf: &[u8], found: F) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { x86::for_each_newline_avx2(buf, found) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { x86::for_each_newline_sse2(buf, found) };
+        }
+    }
+
+    scalar::for_each_newline(buf, 0, found)
+}
+
+// number of chars, provided data is valid UTF-8: all bytes but continuation ones are counted
+pub fn count_chars(buf: &[u8]) -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { x86::count_chars_avx2(buf) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { x86::count_chars_sse2(buf) };
+        }
+    }
+
+    scalar::count_chars(buf)
+}
+
+// number of words delimited by ASCII whitespaces. in_word tells whether the previous data ended
+// inside a word, and is updated for the next call
+pub fn count_words(buf: &[u8], in_word: &mut bool) -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { x86::count_words_avx2(buf, in_word) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { x86::count_words_sse2(buf, in_word) };
+        }
+    }
+
+    scalar::count_words(buf, in_word)
+}
+
+// number of words delimited by any whitespace, as char::is_whitespace() does. Unicode ones are
+// recognised on raw bytes, so data doesn't need to be valid UTF-8 and gives the same words as
+// once decoded. Runs of bytes between them are counted by count_words()
+pub fn count_unicode_words(buf: &[u8], in_word: &mut bool) -> u64 {
+    let mut words = 0;
+    let mut rest = buf;
+
+    // all non-ASCII whitespaces start with one of these bytes
+    while let Some(i) = rest
+        .iter()
+        .position(|&b| matches!(b, 0xC2 | 0xE1 | 0xE2 | 0xE3))
+    {
+        words += count_words(&rest[..i], in_word);
+
+        let length = unicode_blank_length(&rest[i..]);
+        if length > 0 {
+            *in_word = false;
+            rest = &rest[i + length..];
+        } else {
+            words += u64::from(!*in_word);
+            *in_word = true;
+            rest = &rest[i + 1..];
+        }
+    }
+
+    words + count_words(rest, in_word)
+}
+
+// length of the non-ASCII whitespace at the beginning of the buffer, or 0 if there's none
+pub fn unicode_blank_length(buf: &[u8]) -> usize {
+    match buf {
+        // U+0085, U+00A0
+        [0xC2, 0x85, ..] | [0xC2, 0xA0, ..] => 2,
+        // U+1680
+        [0xE1, 0x9A, 0x80, ..] => 3,
+        // U+2000..U+200A, U+2028, U+2029, U+202F
+        [0xE2, 0x80, 0x80..=0x8A, ..] | [0xE2, 0x80, 0xA8, ..] => 3,
+        [0xE2, 0x80, 0xA9, ..] | [0xE2, 0x80, 0xAF, ..] => 3,
+        // U+205F, U+3000
+        [0xE2, 0x81, 0x9F, ..] | [0xE3, 0x80, 0x80, ..] => 3,
+        _ => 0,
+    }
+}
+
+// true if the data starts with a whitespace, either an ASCII or a Unicode one
+pub fn starts_with_blank(buf: &[u8]) -> bool {
+    matches!(buf.first(), Some(&b) if is_ascii_blank(b)) || unicode_blank_length(buf) > 0
+}
+
+// true if the data ends with a whitespace, either an ASCII or a Unicode one
+pub fn ends_with_blank(buf: &[u8]) -> bool {
+    let ends_with = |length: usize| {
+        buf.len() >= length && unicode_blank_length(&buf[buf.len() - length..]) == length
+    };
+    matches!(buf.last(), Some(&b) if is_ascii_blank(b)) || ends_with(2) || ends_with(3)
+}
+
+// same as char::is_whitespace() but for ASCII bytes
+pub fn is_ascii_blank(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | 0x0B | 0x0C | b'\r' | b' ')
+}
+
+// Byte by byte versions, also used for what's left after the vectorised loops, and tested
+// against them
+mod scalar {
+    use super::is_ascii_blank;
+
+    pub fn count_newlines(buf: &[u8]) -> u64 {
+        buf.iter().filter(|&&b| b == b'\n').count() as u64
+    }
+
+    pub fn for_each_newline<F: FnMut(usize)>(buf: &[u8], offset: usize, mut found: F) {
+        for (i, &b) in buf.iter().enumerate() {
+            if b == b'\n' {
+                found(offset + i);
+            }
+        }
+    }
+
+    pub fn count_chars(buf: &[u8]) -> u64 {
+        buf.iter().filter(|&&b| (b as i8) >= -0x40).count() as u64
+    }
+
+    pub fn count_words(buf: &[u8], in_word: &mut bool) -> u64 {
+        let mut words = 0;
+
+        for &b in buf {
+            let blank = is_ascii_blank(b);
+            if !blank && !*in_word {
+                words += 1;
+            }
+            *in_word = !blank;
+        }
+
+        words
+    }
+}
+
+// Each vector of bytes is converted into a bit mask, one bit per byte, and bits are counted
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::scalar;
+
+    // words start on a non-blank byte following a blank one. Previous byte is given by the carry
+    fn word_starts(non_blank: u64, width: u32, in_word: &mut bool) -> u64 {
+        let previous = (non_blank << 1) | u64::from(*in_word);
+        *in_word = non_blank >> (width - 1) & 1 == 1;
+        (non_blank & !previous).count_ones() as u64
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn count_newlines_avx2(buf: &[u8]) -> u64 {
+        let newline = _mm256_set1_epi8(b'\n' as i8);
+        let mut chunks = buf.chunks_exact(32);
+        let mut count = 0;
+
+        for chunk in &mut chunks {
+            let v = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+            let mask = _mm256_movemask_epi8(_mm256_cmpeq_epi8(v, newline)) as u32;
+            count += mask.count_ones() as u64;
+        }
+
+        count + scalar::count_newlines(chunks.remainder())
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn count_newlines_sse2(buf: &[u8]) -> u64 {
+        let newline = _mm_set1_epi8(b'\n' as i8);
+        let mut chunks = buf.chunks_exact(16);
+        let mut count = 0;
+
+        for chunk in &mut chunks {
+            let v = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            let mask = _mm_movemask_epi8(_mm_cmpeq_epi8(v, newline)) as u32;
+            count += mask.count_ones() as u64;
+        }
+
+        count + scalar::count_newlines(chunks.remainder())
+    }
+
+    // bits of the mask are set for newlines, and found in order
+    fn each_bit<F: FnMut(usize)>(mut mask: u32, offset: usize, found: &mut F) {
+        while mask != 0 {
+            found(offset + mask.trailing_zeros() as usize);
+            mask &= mask - 1;
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn for_each_newline_avx2<F: FnMut(usize)>(buf: &[u8], mut found: F) {
+        let newline = _mm256_set1_epi8(b'\n' as i8);
+        let mut chunks = buf.chunks_exact(32);
+
+        for (i, chunk) in (&mut chunks).enumerate() {
+            let v = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+            let mask = _mm256_movemask_epi8(_mm256_cmpeq_epi8(v, newline)) as u32;
+            each_bit(mask, i * 32, &mut found);
+        }
+
+        let offset = buf.len() - chunks.remainder().len();
+        scalar::for_each_newline(chunks.remainder(), offset, found)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn for_each_newline_sse2<F: FnMut(usize)>(buf: &[u8], mut found: F) {
+        let newline = _mm_set1_epi8(b'\n' as i8);
+        let mut chunks = buf.chunks_exact(16);
+
+        for (i, chunk) in (&mut chunks).enumerate() {
+            let v = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            let mask = _mm_movemask_epi8(_mm_cmpeq_epi8(v, newline)) as u32;
+            each_bit(mask, i * 16, &mut found);
+        }
+
+        let offset = buf.len() - chunks.remainder().len();
+        scalar::for_each_newline(chunks.remainder(), offset, found)
+    }
+
+    // continuation bytes are 0x80..=0xBF, i.e. less than -0x40 as signed bytes
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn count_chars_avx2(buf: &[u8]) -> u64 {
+        let limit = _mm256_set1_epi8(-0x41);
+        let mut chunks = buf.chunks_exact(32);
+        let mut count = 0;
+
+        for chunk in &mut chunks {
+            let v = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+            let mask = _mm256_movemask_epi8(_mm256_cmpgt_epi8(v, limit)) as u32;
+            count += mask.count_ones() as u64;
+        }
+
+        count + scalar::count_chars(chunks.remainder())
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn count_chars_sse2(buf: &[u8]) -> u64 {
+        let limit = _mm_set1_epi8(-0x41);
+        let mut chunks = buf.chunks_exact(16);
+        let mut count = 0;
+
+        for chunk in &mut chunks {
+            let v = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            let mask = _mm_movemask_epi8(_mm_cmpgt_epi8(v, limit)) as u32;
+            count += mask.count_ones() as u64;
+        }
+
+        count + scalar::count_chars(chunks.remainder())
+    }
+
+    // blanks are either a space or in the 0x09..=0x0D range
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn count_words_avx2(buf: &[u8], in_word: &mut bool) -> u64 {
+        let space = _mm256_set1_epi8(b' ' as i8);
+        let tab = _mm256_set1_epi8(b'\t' as i8);
+        let range = _mm256_set1_epi8(4);
+        let mut chunks = buf.chunks_exact(32);
+        let mut count = 0;
+
+        for chunk in &mut chunks {
+            let v = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+            let offset = _mm256_sub_epi8(v, tab);
+            let controls = _mm256_cmpeq_epi8(_mm256_min_epu8(offset, range), offset);
+            let blanks = _mm256_or_si256(controls, _mm256_cmpeq_epi8(v, space));
+            let non_blank = !(_mm256_movemask_epi8(blanks) as u32);
+            count += word_starts(u64::from(non_blank), 32, in_word);
+        }
+
+        count + scalar::count_words(chunks.remainder(), in_word)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn count_words_sse2(buf: &[u8], in_word: &mut bool) -> u64 {
+        let space = _mm_set1_epi8(b' ' as i8);
+        let tab = _mm_set1_epi8(b'\t' as i8);
+        let range = _mm_set1_epi8(4);
+        let mut chunks = buf.chunks_exact(16);
+        let mut count = 0;
+
+        for chunk in &mut chunks {
+            let v = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            let offset = _mm_sub_epi8(v, tab);
+            let controls = _mm_cmpeq_epi8(_mm_min_epu8(offset, range), offset);
+            let blanks = _mm_or_si128(controls, _mm_cmpeq_epi8(v, space));
+            let non_blank = !(_mm_movemask_epi8(blanks) as u32) & 0xFFFF;
+            count += word_starts(u64::from(non_blank), 16, in_word);
+        }
+
+        count + scalar::count_words(chunks.remainder(), in_word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // all byte values, in a shuffled order, with runs of blanks and words
+    fn sample() -> Vec<u8> {
+        let mut buf: Vec<u8> = (0..=255u8).map(|b| b.wrapping_mul(97)).collect();
+        buf.extend_from_slice("some words\tαβγ \u{a0}\n\n €uro\r\n  😀 end".as_bytes());
+        buf.repeat(5)
+    }
+
+    #[test]
+    fn same_as_scalar() {
+        let buf = sample();
+
+        // every length and alignment, to exercise vectorised loops and remainders
+        for start in 0..64 {
+            for end in (start..buf.len()).step_by(7) {
+                let data = &buf[start..end];
+
+                assert_eq!(count_newlines(data), scalar::count_newlines(data));
+                assert_eq!(count_chars(data), scalar::count_chars(data));
+
+                let (mut simd, mut scalar) = (Vec::new(), Vec::new());
+                for_each_newline(data, |i| simd.push(i));
+                scalar::for_each_newline(data, 0, |i| scalar.push(i));
+                assert_eq!(simd, scalar);
+
+                for &in_word in &[false, true] {
+                    let (mut simd, mut scalar) = (in_word, in_word);
+                    assert_eq!(
+                        count_words(data, &mut simd),
+                        scalar::count_words(data, &mut scalar)
+                    );
+                    assert_eq!(simd, scalar);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn sse2_same_as_scalar() {
+        let buf = sample();
+
+        for start in 0..32 {
+            let data = &buf[start..];
+            let (mut simd, mut scalar) = (false, false);
+
+            unsafe {
+                assert_eq!(x86::count_newlines_sse2(data), scalar::count_newlines(data));
+                assert_eq!(x86::count_chars_sse2(data), scalar::count_chars(data));
+
+                let (mut vectorised, mut expected) = (Vec::new(), Vec::new());
+                x86::for_each_newline_sse2(data, |i| vectorised.push(i));
+                scalar::for_each_newline(data, 0, |i| expected.push(i));
+                assert_eq!(vectorised, expected);
+                assert_eq!(
+                    x86::count_words_sse2(data, &mut simd),
+                    scalar::count_words(data, &mut scalar)
+                );
+            }
+            assert_eq!(simd, scalar);
+        }
+    }
+
+    #[test]
+    fn count_utf8() {
+        let text = "αβγ δ\n€uro 😀 \u{a0}x\nlast line";
+
+        assert_eq!(count_chars(text.as_bytes()), text.chars().count() as u64);
+        assert_eq!(count_newlines(text.as_bytes()), 2);
+
+        let mut in_word = false;
+        assert_eq!(count_words(b"  two words\t", &mut in_word), 2);
+        assert!(!in_word);
+        assert_eq!(count_words(b"split", &mut in_word), 1);
+        assert_eq!(count_words(b"ted ", &mut in_word), 0);
+    }
+
+    #[test]
+    fn unicode_words() {
+        // all the whitespaces known to char::is_whitespace()
+        let blanks: Vec<char> = (0..=0x10FFFFu32)
+            .filter_map(std::char::from_u32)
+            .filter(|c| c.is_whitespace())
+            .collect();
+
+        for blank in &blanks {
+            let text = format!("a{}b{}", blank, blank);
+            let mut in_word = false;
+            assert_eq!(count_unicode_words(text.as_bytes(), &mut in_word), 2);
+            assert!(!in_word);
+            assert!(ends_with_blank(text.as_bytes()));
+            assert!(starts_with_blank(&text.as_bytes()[1..]));
+        }
+
+        // not decoded, so invalid sequences are part of words
+        let text = "αβγ δ\n€uro 😀 \u{a0}x\u{3000}\u{2003}last line\u{85}";
+        let mut in_word = false;
+        assert_eq!(
+            count_unicode_words(text.as_bytes(), &mut in_word),
+            text.split_whitespace().count() as u64
+        );
+        assert_eq!(count_unicode_words(b"a\xC2 \xE2\x80b\xE3", &mut false), 2);
+        assert!(!starts_with_blank("\u{2031}".as_bytes()));
+        assert!(!ends_with_blank(b"a\xA0"));
+    }
+}
@@ -1,11 +1,14 @@
+use std::io::{self, Write};
 use std::iter::Sum;
 use std::ops::AddAssign;
 use std::path::Path;
 
-use crate::options::CliOptions;
+use crate::config::{Config, InvalidMode};
+use crate::metric::{Builtin, Metric, MetricValue};
 
-// As arguments are related to final statistics, use this to not redefine structs
+/// As arguments are related to final statistics, use this to not redefine structs
 #[derive(Debug, Default)]
+#[non_exhaustive]
 pub struct Stats {
     pub bytes: u64,
     pub chars: u64,
@@ -13,51 +16,140 @@ pub struct Stats {
     pub lines: u64,
     pub max_line: u64,
     pub min_line: u64,
+
+    /// size of the data before decompression
+    pub compressed_bytes: u64,
+
+    /// number of invalid UTF-8 sequences replaced
+    pub invalid_sequences: u64,
+
+    /// name of the encoding, when it's guessed
+    pub encoding: Option<&'static str>,
+
+    /// with salvage, offset in the (decompressed) data where a read error stopped the counting
+    pub partial: Option<u64>,
+
+    /// original file name and modification time, from the gzip header
+    pub original_name: Option<String>,
+    pub mtime: Option<u32>,
+
+    /// values of the extra metrics of the configuration, in the same order
+    pub metrics: Vec<MetricValue>,
 }
 
 // This is used for displaying the final result
 impl Stats {
-    pub fn print_results<P: AsRef<Path>>(&self, opt: &CliOptions, f: P) {
-        if opt.bytes {
-            print!("{:8} ", self.bytes);
-        }
+    /// metrics and values of the requested counts, in output order: the built-in ones, then the
+    /// extra metrics
+    pub fn values(&self, opt: &Config) -> Vec<(&dyn Metric, u64)> {
+        let builtins: &'static [Builtin] = &Builtin::ALL;
+
+        builtins
+            .iter()
+            .filter(|b| b.requested(opt))
+            .map(|b| (b as &dyn Metric, b.value(self)))
+            .chain(
+                self.metrics
+                    .iter()
+                    .map(|(metric, value)| (metric.as_ref(), *value)),
+            )
+            .collect()
+    }
+
+    pub fn print_results<P: AsRef<Path>>(&self, opt: &Config, f: P) {
+        self.write_results(&mut io::stdout().lock(), opt, f)
+            .expect("failed printing to stdout");
+    }
 
-        if opt.chars {
-            print!("{:8} ", self.chars);
+    /// same as print_results(), but to any writer
+    pub fn write_results<W: Write, P: AsRef<Path>>(
+        &self,
+        out: &mut W,
+        opt: &Config,
+        f: P,
+    ) -> io::Result<()> {
+        for (metric, value) in self.values(opt) {
+            write!(out, "{} ", metric.render(value))?;
         }
 
-        if opt.words {
-            print!("{:8} ", self.words);
+        // ratio is computed here to get a correct value for totals
+        if opt.ratio {
+            write!(out, "{:8} {:8} ", self.compressed_bytes, self.bytes)?;
+            if self.bytes == 0 {
+                write!(out, "{:>8} ", "-")?;
+            } else {
+                write!(
+                    out,
+                    "{:8.3} ",
+                    self.compressed_bytes as f64 / self.bytes as f64
+                )?;
+            }
         }
 
-        if opt.lines {
-            print!("{:8} ", self.lines);
+        if opt.invalid == InvalidMode::Report {
+            write!(out, "{:8} ", self.invalid_sequences)?;
         }
 
-        if opt.min_line {
-            print!("{:8} ", self.min_line);
+        if opt.detect_encoding {
+            write!(out, "{:14} ", self.encoding.unwrap_or(""))?;
         }
 
-        if opt.max_line {
-            print!("{:8} ", self.max_line);
+        if opt.gzip_header {
+            let mtime = match self.mtime {
+                Some(mtime) if mtime != 0 => format_timestamp(mtime),
+                _ => "-".to_string(),
+            };
+            write!(
+                out,
+                "{:19} {:16} ",
+                mtime,
+                self.original_name.as_deref().unwrap_or("-")
+            )?;
+        }
+        if opt.salvage {
+            write!(out, "{} ", if self.partial.is_some() { '!' } else { ' ' })?;
         }
-        println!("{}", f.as_ref().display());
+
+        writeln!(out, "{}", f.as_ref().display())
     }
 }
 
+// format a UNIX timestamp as an UTC date and time (YYYY-MM-DD HH:MM:SS)
+fn format_timestamp(timestamp: u32) -> String {
+    let days = i64::from(timestamp / 86400);
+    let seconds = timestamp % 86400;
+
+    // convert days since the epoch to a civil date (see http://howardhinnant.github.io/date_algorithms.html)
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
 /// Sum is used to sum all stats
 impl<'a> Sum<&'a Self> for Stats {
     fn sum<I>(iter: I) -> Self
     where
         I: Iterator<Item = &'a Self>,
     {
-        iter.fold(Self::default(), |a, b| Self {
-            bytes: a.bytes + b.bytes,
-            chars: a.chars + b.chars,
-            words: a.words + b.words,
-            lines: a.lines + b.lines,
-            min_line: a.min_line + b.min_line,
-            max_line: a.max_line + b.max_line,
+        iter.fold(Self::default(), |mut a, b| {
+            a.add(b);
+            a
         })
     }
 }
@@ -65,18 +157,44 @@ impl<'a> Sum<&'a Self> for Stats {
 /// Used to add 2 stats
 impl AddAssign for Stats {
     fn add_assign(&mut self, other: Self) {
-        *self = Self {
-            bytes: self.bytes + other.bytes,
-            chars: self.chars + other.chars,
-            words: self.words + other.words,
-            lines: self.lines + other.lines,
-            min_line: self.min_line + other.min_line,
-            max_line: self.max_line + other.max_line,
+        self.add(&other);
+    }
+}
+
+impl Stats {
+    // add the stats of another input, each count being merged by its metric
+    fn add(&mut self, other: &Stats) {
+        for &b in &Builtin::ALL {
+            let value = b.merge(b.value(self), b.value(other));
+            *b.value_mut(self) = value;
         }
+        self.metrics = merge_metrics(std::mem::take(&mut self.metrics), &other.metrics);
+
+        self.compressed_bytes += other.compressed_bytes;
+        self.invalid_sequences += other.invalid_sequences;
+        self.encoding = None;
+        self.partial = None;
+        self.original_name = None;
+        self.mtime = None;
     }
 }
 
+// merge the values of the extra metrics, each one in its own way
+fn merge_metrics(mut total: Vec<MetricValue>, other: &[MetricValue]) -> Vec<MetricValue> {
+    // totals start without any value
+    if total.is_empty() {
+        return other.to_vec();
+    }
+
+    for ((metric, value), (_, other)) in total.iter_mut().zip(other) {
+        *value = metric.merge(*value, *other);
+    }
+
+    total
+}
+
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     use super::*;
 
@@ -107,4 +225,11 @@ mod tests {
         assert_eq!(stats2.max_line, 2);
         assert_eq!(stats2.min_line, 2);
     }
+
+    #[test]
+    fn format_timestamp() {
+        assert_eq!(super::format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(super::format_timestamp(1613844312), "2021-02-20 18:05:12");
+        assert_eq!(super::format_timestamp(951782400), "2000-02-29 00:00:00");
+    }
 }
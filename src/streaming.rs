@@ -0,0 +1,127 @@
+use std::io::Error;
+
+use crate::chunk::ChunkStats;
+use crate::config::{Config, InvalidMode};
+use crate::metric::Metric;
+use crate::stats::Stats;
+
+/// Counts data given piece by piece, e.g. as it arrives from the network. Pieces can be split
+/// anywhere, even in the middle of a UTF-8 sequence, a word or a line: results are the same as
+/// when counting the whole data with [`Counter::read_file`](crate::Counter::read_file).
+///
+/// ```
+/// use awc::{Config, StreamingCounter};
+///
+/// let mut counter = StreamingCounter::new(&Config::all());
+/// counter.update(b"hello wo");
+/// counter.update(b"rld\n\xCE");
+/// counter.update(b"\xB1");
+///
+/// let stats = counter.finish()?;
+/// assert_eq!(stats.words, 3);
+/// assert_eq!(stats.chars, 13);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct StreamingCounter {
+    config: Config,
+    chunk: ChunkStats,
+
+    // extra metrics of the configuration, observing the same data
+    metrics: Vec<Box<dyn Metric>>,
+}
+
+impl StreamingCounter {
+    /// start counting with the given configuration
+    pub fn new(config: &Config) -> StreamingCounter {
+        StreamingCounter {
+            config: config.clone(),
+            chunk: ChunkStats::new(&[], config),
+            metrics: config.metrics.iter().map(|m| m.fresh()).collect(),
+        }
+    }
+
+    /// count the next piece of data
+    pub fn update(&mut self, buf: &[u8]) {
+        let chunk = std::mem::replace(&mut self.chunk, ChunkStats::new(&[], &self.config));
+        self.chunk = chunk.merge(ChunkStats::new(buf, &self.config));
+
+        for metric in &mut self.metrics {
+            metric.observe(buf);
+        }
+    }
+
+    /// true if an invalid UTF-8 sequence was met, and the configuration makes it an error.
+    /// Then there's no need to give more data
+    pub fn failed(&self) -> bool {
+        self.config.invalid == InvalidMode::Strict && self.chunk.has_invalid()
+    }
+
+    /// get the statistics once all data is given. Fails on invalid UTF-8 if the configuration
+    /// says so
+    pub fn finish(self) -> Result<Stats, Error> {
+        let mut stats = self.chunk.finish(&self.config)?;
+
+        stats.metrics = self
+            .config
+            .metrics
+            .iter()
+            .cloned()
+            .zip(self.metrics)
+            .map(|(metric, mut state)| {
+                let value = state.finalize(&stats);
+                (metric, value)
+            })
+            .collect();
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::Counter;
+
+    #[test]
+    fn update() {
+        let mut config = Config::all();
+        config.invalid = InvalidMode::Lossy;
+
+        for file_name in &["tests/poe.unix", "tests/utf8.txt", "tests/invalid.txt"] {
+            let data = std::fs::read(file_name).unwrap();
+            let expected = Counter::read_file(&data[..], &config).unwrap();
+
+            // pieces of varying sizes
+            for size in 1..=13 {
+                let mut counter = StreamingCounter::new(&config);
+                for (i, piece) in data.chunks(size).enumerate() {
+                    counter.update(&piece[..piece.len().min(i % size + 1)]);
+                    counter.update(&piece[piece.len().min(i % size + 1)..]);
+                }
+                let stats = counter.finish().unwrap();
+
+                assert_eq!(stats.bytes, expected.bytes);
+                assert_eq!(stats.chars, expected.chars);
+                assert_eq!(stats.words, expected.words);
+                assert_eq!(stats.lines, expected.lines);
+                assert_eq!(stats.max_line, expected.max_line);
+                assert_eq!(stats.min_line, expected.min_line);
+                assert_eq!(stats.invalid_sequences, expected.invalid_sequences);
+            }
+        }
+
+        // same error as read_file()
+        let data = std::fs::read("tests/invalid.txt").unwrap();
+        let mut counter = StreamingCounter::new(&Config::all());
+        for piece in data.chunks(3) {
+            counter.update(piece);
+        }
+        assert!(counter.failed());
+        assert_eq!(
+            counter.finish().unwrap_err().to_string(),
+            Counter::read_file(&data[..], &Config::all())
+                .unwrap_err()
+                .to_string()
+        );
+    }
+}
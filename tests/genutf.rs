@@ -1,12 +1,15 @@
 // generate UTF-8 to UFT-32 file samples
 use std::char;
+use std::io::{self, Write};
 
 use clap::{App, Arg};
 
 enum Encoding {
     UTF8,
-    UTF16,
-    UTF32,
+    UTF16LE,
+    UTF16BE,
+    UTF32LE,
+    UTF32BE,
 }
 
 const LAST_CHAR_INDEX: u32 = 1000;
@@ -21,7 +24,7 @@ fn main() {
                 .short('e')
                 .long("encoding")
                 .required(true)
-                .possible_values(&["utf8", "utf16", "utf32"])
+                .possible_values(&["utf8", "utf16le", "utf16be", "utf32le", "utf32be"])
                 .takes_value(true),
         )
         .arg(
@@ -36,43 +39,47 @@ fn main() {
 
     // get encoding & bom
     let encoding = match matches.value_of("encoding").unwrap() {
-        "uft8" => Encoding::UTF8,
-        "uft16" => Encoding::UTF16,
-        "uft32" => Encoding::UTF32,
+        "utf8" => Encoding::UTF8,
+        "utf16le" => Encoding::UTF16LE,
+        "utf16be" => Encoding::UTF16BE,
+        "utf32le" => Encoding::UTF32LE,
+        "utf32be" => Encoding::UTF32BE,
         _ => Encoding::UTF8,
     };
 
     let bom = matches.is_present("bom");
 
-    // write 1000 chars
-    match encoding {
-        Encoding::UTF8 => write_utf8(),
-        Encoding::UTF16 => write_utf16(),
-        _ => todo!("todo"),
+    // encode the BOM like any other char
+    let mut chars: Vec<char> = Vec::new();
+    if bom {
+        chars.push('\u{FEFF}');
     }
-}
 
-// write utf-8 chars
-fn write_utf8() {
-    for i in 32..LAST_CHAR_INDEX + 32 {
-        match char::from_u32(i) {
-            Some(c) => print!("{}", c),
-            None => (),
-        }
-    }
-}
+    // write 1000 chars
+    chars.extend((32..LAST_CHAR_INDEX + 32).filter_map(char::from_u32));
 
-// write utf-16 chars
-fn write_utf16() {
-    for i in 32..LAST_CHAR_INDEX + 32 {
-        match char::from_u32(i) {
-            Some(c) => {
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in chars {
+        match encoding {
+            Encoding::UTF8 => {
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            }
+            Encoding::UTF16LE | Encoding::UTF16BE => {
                 let mut buffer = [0; 2];
-                let utf16_char = c.encode_utf16(&mut buffer);
-                print!("{}", utf16_char[0]);
-                print!("{}", utf16_char[1]);
+                for unit in c.encode_utf16(&mut buffer) {
+                    match encoding {
+                        Encoding::UTF16LE => bytes.extend_from_slice(&unit.to_le_bytes()),
+                        _ => bytes.extend_from_slice(&unit.to_be_bytes()),
+                    }
+                }
             }
-            None => (),
+            Encoding::UTF32LE => bytes.extend_from_slice(&(c as u32).to_le_bytes()),
+            Encoding::UTF32BE => bytes.extend_from_slice(&(c as u32).to_be_bytes()),
         }
     }
+
+    io::stdout()
+        .write_all(&bytes)
+        .expect("unable to write to stdout");
 }